@@ -0,0 +1,126 @@
+// adapt
+// wraps wasip1 core modules into components with the WASI preview1 adapter
+//
+// The adapter comes from wasi-preview1-component-adapter-provider 29, whose
+// adapters import wasi@0.2.3 interfaces. Its version does not have to follow
+// wasmtime's: the engine links any wasi@0.2.x import to its own 0.2
+// implementation, so an adapter only has to be new enough for the 0.2
+// interfaces guests use. It is bumped on its own when a guest needs more.
+
+use anyhow::{bail, Context};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use wasi_preview1_component_adapter_provider::{
+    WASI_SNAPSHOT_PREVIEW1_ADAPTER_NAME, WASI_SNAPSHOT_PREVIEW1_COMMAND_ADAPTER,
+    WASI_SNAPSHOT_PREVIEW1_REACTOR_ADAPTER,
+};
+use wasmparser::{Parser, Payload};
+
+/// What a core module imports and embeds, as far as adapting it is concerned.
+struct CoreModule {
+    import_modules: Vec<String>,
+    exports_start: bool,
+    has_component_type: bool,
+}
+
+impl CoreModule {
+    fn parse(wasm_bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut module = CoreModule {
+            import_modules: Vec::new(),
+            exports_start: false,
+            has_component_type: false,
+        };
+        for payload in Parser::new(0).parse_all(wasm_bytes) {
+            match payload.context("module failed to parse")? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        let import = import.context("module failed to parse")?;
+                        if !module.import_modules.iter().any(|m| m == import.module) {
+                            module.import_modules.push(import.module.to_string());
+                        }
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        module.exports_start |=
+                            export.context("module failed to parse")?.name == "_start";
+                    }
+                }
+                Payload::CustomSection(reader) if reader.name().starts_with("component-type") => {
+                    module.has_component_type = true;
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn imports(&self, name: &str) -> bool {
+        self.import_modules.iter().any(|m| m == name)
+    }
+}
+
+/// SHA-256 of the adapters modules are wrapped with, so that a component
+/// cached under the module it was adapted from is not reused once the
+/// adapter changes.
+pub fn adapter_digest() -> &'static [u8] {
+    static DIGEST: OnceLock<[u8; 32]> = OnceLock::new();
+    DIGEST.get_or_init(|| {
+        let mut hasher = Sha256::new();
+        hasher.update(WASI_SNAPSHOT_PREVIEW1_REACTOR_ADAPTER);
+        hasher.update(WASI_SNAPSHOT_PREVIEW1_COMMAND_ADAPTER);
+        hasher.finalize().into()
+    })
+}
+
+/// Returns `wasm_bytes` as a component: components as they are, and wasip1
+/// core modules built with wit-bindgen wrapped with the WASI preview1
+/// adapter. Anything else fails with what the module is and how to build
+/// one op-sim can run.
+pub fn componentize(wasm_bytes: &[u8]) -> anyhow::Result<Cow<'_, [u8]>> {
+    if Parser::is_component(wasm_bytes) {
+        return Ok(Cow::Borrowed(wasm_bytes));
+    }
+    if !Parser::is_core_wasm(wasm_bytes) {
+        bail!("file is not a WebAssembly module or component");
+    }
+
+    let module = CoreModule::parse(wasm_bytes)?;
+    if module.imports("wasi_unstable") {
+        bail!(
+            "this is a wasi_unstable core module (pre-wasip1), which cannot be adapted; \
+             build for wasm32-wasip2 to get a component"
+        );
+    }
+    if !module.has_component_type {
+        let target = if module.imports(WASI_SNAPSHOT_PREVIEW1_ADAPTER_NAME) {
+            "a wasip1 core module (e.g. built for wasm32-wasip1)"
+        } else {
+            "a plain core module"
+        };
+        bail!(
+            "this is {target} without wit-bindgen component metadata, so it does not say which world it \
+             implements and cannot be adapted; generate bindings for the newton:provider world with \
+             wit-bindgen and build for wasm32-wasip2 (or wasm32-wasip1, which op-sim adapts)"
+        );
+    }
+
+    let mut encoder = wit_component::ComponentEncoder::default()
+        .validate(true)
+        .module(wasm_bytes)
+        .context("failed to read the module's component metadata")?;
+    if module.imports(WASI_SNAPSHOT_PREVIEW1_ADAPTER_NAME) {
+        // a module with `_start` is a command; libraries exporting `run` are reactors
+        let adapter = if module.exports_start {
+            WASI_SNAPSHOT_PREVIEW1_COMMAND_ADAPTER
+        } else {
+            WASI_SNAPSHOT_PREVIEW1_REACTOR_ADAPTER
+        };
+        encoder = encoder.adapter(WASI_SNAPSHOT_PREVIEW1_ADAPTER_NAME, adapter)?;
+    }
+    let component = encoder.encode().context(
+        "failed to adapt the wasip1 module to a component; build for wasm32-wasip2 instead",
+    )?;
+    Ok(Cow::Owned(component))
+}
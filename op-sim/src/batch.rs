@@ -0,0 +1,118 @@
+// batch
+// runs one compiled component over many inputs concurrently
+
+use crate::envelope::{Envelope, Status};
+use crate::metrics::Metrics;
+use crate::sim::{GuestOutput, Simulation};
+use anyhow::Context;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Serialize)]
+pub struct BatchRecord {
+    pub index: usize,
+    pub input: String,
+    #[serde(flatten)]
+    pub envelope: Envelope,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+}
+
+/// Reads one input per non-empty line. A line holding a JSON string is passed
+/// to the guest unquoted; any other line is passed as-is.
+pub fn read_inputs(path: &Path) -> anyhow::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read inputs {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(
+            |line| match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(serde_json::Value::String(input)) => input,
+                _ => line.to_string(),
+            },
+        )
+        .collect())
+}
+
+/// How many inputs a batch ran and how many of them failed.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub failed: usize,
+}
+
+/// Runs every input with at most `concurrency` runs in flight, writing each
+/// record to `writer` as a JSON line as soon as its run completes, so records
+/// come in completion order and carry their input's `index`.
+pub async fn run_batch(
+    simulation: Arc<Simulation>,
+    inputs: Vec<String>,
+    concurrency: usize,
+    mut writer: impl Write,
+) -> anyhow::Result<BatchSummary> {
+    let total = inputs.len();
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for (index, input) in inputs.into_iter().enumerate() {
+        let simulation = Arc::clone(&simulation);
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            let (envelope, metrics) = match simulation.run(&input, &GuestOutput::capture()).await {
+                Ok(run) => {
+                    let mut envelope = Envelope::new(
+                        &run.outcome,
+                        &run.guest_stdout,
+                        &run.guest_stderr,
+                        run.timings,
+                    );
+                    envelope.faults = run.http.applied_faults().to_vec();
+                    envelope.logs = run.logs;
+                    envelope.logs_dropped = run.logs_dropped;
+                    (envelope, Some(run.metrics))
+                }
+                Err(e) => (Envelope::new(&Err(e), &[], &[], Default::default()), None),
+            };
+            BatchRecord {
+                index,
+                input,
+                envelope,
+                metrics,
+            }
+        });
+    }
+
+    let mut summary = BatchSummary { total, failed: 0 };
+    let mut completed = 0;
+    while let Some(record) = tasks.join_next().await {
+        let record = record.expect("batch task panicked");
+        completed += 1;
+        if record.envelope.status == Status::Error {
+            summary.failed += 1;
+        }
+        eprintln!(
+            "[{}/{}] input #{}: {}",
+            completed,
+            total,
+            record.index,
+            match record.envelope.status {
+                Status::Ok => "ok",
+                Status::Error => "error",
+            }
+        );
+        serde_json::to_writer(&mut writer, &record)?;
+        writeln!(writer)?;
+        writer.flush()?;
+    }
+    Ok(summary)
+}
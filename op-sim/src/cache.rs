@@ -0,0 +1,231 @@
+// cache
+// on-disk cache of precompiled components, keyed by wasm content and engine config
+
+use crate::adapt;
+use anyhow::Context;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use wasmtime::component::Component;
+use wasmtime::Engine;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct CacheArgs {
+    /// Directory for cached precompiled components [default: $XDG_CACHE_HOME/op-sim or ~/.cache/op-sim]
+    #[arg(long, value_name = "DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Always compile the component, without reading or writing the cache
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+impl CacheArgs {
+    pub fn cache(&self) -> Option<ComponentCache> {
+        if self.no_cache {
+            return None;
+        }
+        self.cache_dir
+            .clone()
+            .or_else(default_cache_dir)
+            .map(ComponentCache::new)
+    }
+}
+
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("op-sim"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Disabled,
+}
+
+impl std::fmt::Display for CacheStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CacheStatus::Hit => "cache hit",
+            CacheStatus::Miss => "cache miss",
+            CacheStatus::Disabled => "cache disabled",
+        })
+    }
+}
+
+/// Cache entries are named after the SHA-256 of the wasm bytes and of the
+/// engine's precompile compatibility hash, so a change to either simply misses.
+/// A wasip1 core module is cached under its own bytes and the adapter it is
+/// wrapped with, so a hit skips adapting it as well as compiling it.
+/// Each entry starts with the SHA-256 of the serialized artifact that follows
+/// it, and entries that fail that check or fail to deserialize are discarded
+/// and recompiled.
+///
+/// Loading an entry runs the machine code in it, so on Unix the directory is
+/// created private to the current user, and it is not used at all when it is
+/// owned by someone else or writable by others. Entries owned by another user
+/// are discarded.
+#[derive(Debug, Clone)]
+pub struct ComponentCache {
+    dir: PathBuf,
+}
+
+impl ComponentCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn load_or_compile(
+        &self,
+        engine: &Engine,
+        wasm_bytes: &[u8],
+    ) -> anyhow::Result<(Component, CacheStatus)> {
+        if let Err(e) = self.prepare_dir() {
+            eprintln!("Warning: not using the component cache: {e:#}");
+            let component = Component::new(engine, adapt::componentize(wasm_bytes)?)?;
+            return Ok((component, CacheStatus::Disabled));
+        }
+        let path = self.entry_path(engine, wasm_bytes);
+
+        if let Some(component) = self.load(engine, &path) {
+            return Ok((component, CacheStatus::Hit));
+        }
+
+        let component = Component::new(engine, adapt::componentize(wasm_bytes)?)?;
+        if let Err(e) = self.store(&path, &component) {
+            eprintln!(
+                "Warning: failed to write component cache entry {}: {e:#}",
+                path.display()
+            );
+        }
+        Ok((component, CacheStatus::Miss))
+    }
+
+    fn entry_path(&self, engine: &Engine, wasm_bytes: &[u8]) -> PathBuf {
+        let mut engine_hasher = Sha256Hasher(Sha256::new());
+        engine
+            .precompile_compatibility_hash()
+            .hash(&mut engine_hasher);
+        env!("CARGO_PKG_VERSION").hash(&mut engine_hasher);
+        let engine_hash = hex::encode(engine_hasher.0.finalize());
+        let mut wasm_hasher = Sha256::new();
+        wasm_hasher.update(wasm_bytes);
+        if wasmparser::Parser::is_core_wasm(wasm_bytes) {
+            wasm_hasher.update(adapt::adapter_digest());
+        }
+        let wasm_hash = hex::encode(wasm_hasher.finalize());
+        self.dir
+            .join(format!("{wasm_hash}-{}.cwasm", &engine_hash[..16]))
+    }
+
+    // creates the cache directory private to the current user, or checks that
+    // an existing one is theirs and that nobody else can write to it
+    fn prepare_dir(&self) -> anyhow::Result<()> {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&self.dir)
+            .with_context(|| format!("failed to create cache dir {}", self.dir.display()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            let metadata = std::fs::metadata(&self.dir)
+                .with_context(|| format!("failed to read cache dir {}", self.dir.display()))?;
+            if !owned_by_current_user(&metadata) {
+                anyhow::bail!(
+                    "cache dir {} is owned by another user (uid {})",
+                    self.dir.display(),
+                    metadata.uid()
+                );
+            }
+            if metadata.permissions().mode() & 0o022 != 0 {
+                anyhow::bail!(
+                    "cache dir {} is writable by other users",
+                    self.dir.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&self, engine: &Engine, path: &Path) -> Option<Component> {
+        let metadata = std::fs::symlink_metadata(path).ok()?;
+        #[cfg(unix)]
+        if !owned_by_current_user(&metadata) {
+            eprintln!(
+                "Warning: discarding component cache entry {} owned by another user",
+                path.display()
+            );
+            let _ = std::fs::remove_file(path);
+            return None;
+        }
+        if !metadata.is_file() {
+            return None;
+        }
+        let entry = std::fs::read(path).ok()?;
+        let component = entry
+            .split_at_checked(32)
+            .filter(|(checksum, artifact)| *checksum == &Sha256::digest(artifact)[..])
+            // SAFETY: the checksum only catches entries that were truncated or
+            // partially written: it is unkeyed and stored next to the artifact,
+            // so anyone who can write the entry can forge it. The entry is
+            // trusted because it is owned by the current user in a directory
+            // only they can write to (see `prepare_dir`), so it was written by
+            // `store` from `Component::serialize` in one of their own runs.
+            // Wasmtime additionally rejects artifacts from an incompatible
+            // engine or version.
+            .and_then(|(_, artifact)| unsafe { Component::deserialize(engine, artifact) }.ok());
+        if component.is_none() {
+            eprintln!(
+                "Warning: discarding invalid component cache entry {}",
+                path.display()
+            );
+            let _ = std::fs::remove_file(path);
+        }
+        component
+    }
+
+    fn store(&self, path: &Path, component: &Component) -> anyhow::Result<()> {
+        let artifact = component.serialize()?;
+        let mut entry = Sha256::digest(&artifact).to_vec();
+        entry.extend_from_slice(&artifact);
+
+        // write to a temporary file first so readers never see a partial entry
+        let tmp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+        std::fs::write(&tmp_path, &entry)?;
+        std::fs::rename(&tmp_path, path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp_path);
+        })?;
+        Ok(())
+    }
+}
+
+/// Feeds `Hash` output into SHA-256 so the key is the same from run to run,
+/// unlike a randomly seeded `DefaultHasher`.
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+    }
+}
+
+#[cfg(unix)]
+fn owned_by_current_user(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    // SAFETY: geteuid has no preconditions and cannot fail
+    metadata.uid() == unsafe { libc::geteuid() }
+}
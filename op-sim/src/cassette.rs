@@ -0,0 +1,275 @@
+// cassette
+// records and replays http fetches made by a guest so runs can be reproduced offline
+
+use crate::newton::provider::http::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A body stored as text when it is valid UTF-8, otherwise as hex.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "encoding", content = "data", rename_all = "lowercase")]
+pub enum Body {
+    Utf8(String),
+    Hex(String),
+}
+
+impl Body {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Body::Utf8(text.to_string()),
+            Err(_) => Body::Hex(hex::encode(bytes)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Body::Utf8(text) => Ok(text.as_bytes().to_vec()),
+            Body::Hex(data) => hex::decode(data).context("invalid hex body in cassette"),
+        }
+    }
+}
+
+/// A recorded request. Headers are not kept: they commonly carry
+/// credentials, and cassettes are meant to be committed as fixtures.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Body>,
+}
+
+impl RecordedRequest {
+    pub fn from_request(request: &HttpRequest) -> Self {
+        Self {
+            method: request.method.clone(),
+            url: request.url.clone(),
+            body: request.body.as_deref().map(Body::from_bytes),
+        }
+    }
+
+    /// Requests match on method, url and body.
+    fn matches(&self, request: &HttpRequest) -> bool {
+        self.method.eq_ignore_ascii_case(&request.method)
+            && self.url == request.url
+            && self.body == request.body.as_deref().map(Body::from_bytes)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Response(RecordedResponse),
+    Error(String),
+}
+
+impl Outcome {
+    pub fn from_result(result: &Result<HttpResponse, String>) -> Self {
+        match result {
+            Ok(response) => Outcome::Response(RecordedResponse {
+                status: response.status,
+                headers: response.headers.clone(),
+                body: Body::from_bytes(&response.body),
+            }),
+            Err(error) => Outcome::Error(error.clone()),
+        }
+    }
+
+    pub fn to_result(&self) -> Result<HttpResponse, String> {
+        match self {
+            Outcome::Response(response) => Ok(HttpResponse {
+                status: response.status,
+                headers: response.headers.clone(),
+                body: response.body.to_bytes().map_err(|e| e.to_string())?,
+            }),
+            Outcome::Error(error) => Err(error.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read cassette {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse cassette {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, contents)
+            .with_context(|| format!("failed to write cassette {}", path.display()))
+    }
+
+    pub fn record(&mut self, request: &HttpRequest, result: &Result<HttpResponse, String>) {
+        self.interactions.push(Interaction {
+            request: RecordedRequest::from_request(request),
+            outcome: Outcome::from_result(result),
+        });
+    }
+}
+
+/// Serves interactions from a cassette. Each recorded interaction is used at
+/// most once, in recording order, so repeated identical requests replay the
+/// responses they originally received.
+pub struct Replayer {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+impl Replayer {
+    pub fn new(cassette: Cassette) -> Self {
+        let used = vec![false; cassette.interactions.len()];
+        Self {
+            interactions: cassette.interactions,
+            used,
+        }
+    }
+
+    pub fn replay(&mut self, request: &HttpRequest) -> Option<Result<HttpResponse, String>> {
+        let index = self
+            .interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| !self.used[i] && interaction.request.matches(request))?;
+        self.used[index] = true;
+        Some(self.interactions[index].outcome.to_result())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, url: &str, body: Option<&[u8]>) -> HttpRequest {
+        HttpRequest {
+            url: url.to_string(),
+            method: method.to_string(),
+            headers: Vec::new(),
+            body: body.map(<[u8]>::to_vec),
+        }
+    }
+
+    fn response(body: &[u8]) -> Result<HttpResponse, String> {
+        Ok(HttpResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: body.to_vec(),
+        })
+    }
+
+    fn body(result: Option<Result<HttpResponse, String>>) -> Vec<u8> {
+        result.unwrap().unwrap().body
+    }
+
+    #[test]
+    fn replays_repeated_requests_in_recording_order() {
+        let mut cassette = Cassette::default();
+        let price = request("GET", "https://api.example.com/price", None);
+        cassette.record(&price, &response(b"1"));
+        cassette.record(&price, &response(b"2"));
+
+        let mut replayer = Replayer::new(cassette);
+        assert_eq!(body(replayer.replay(&price)), b"1");
+        assert_eq!(body(replayer.replay(&price)), b"2");
+        assert!(replayer.replay(&price).is_none());
+    }
+
+    #[test]
+    fn matches_on_method_url_and_body_but_not_headers() {
+        let mut cassette = Cassette::default();
+        cassette.record(
+            &request("POST", "http://rpc", Some(b"{\"id\":1}")),
+            &response(b"one"),
+        );
+        cassette.record(
+            &request("POST", "http://rpc", Some(b"{\"id\":2}")),
+            &response(b"two"),
+        );
+
+        let mut replayer = Replayer::new(cassette);
+        assert!(replayer
+            .replay(&request("GET", "http://rpc", None))
+            .is_none());
+        assert!(replayer
+            .replay(&request("POST", "http://rpc/", Some(b"{\"id\":1}")))
+            .is_none());
+        let mut second = request("post", "http://rpc", Some(b"{\"id\":2}"));
+        second
+            .headers
+            .push(("authorization".to_string(), "Bearer token".to_string()));
+        assert_eq!(body(replayer.replay(&second)), b"two");
+        assert_eq!(
+            body(replayer.replay(&request("POST", "http://rpc", Some(b"{\"id\":1}")))),
+            b"one"
+        );
+    }
+
+    #[test]
+    fn does_not_write_request_headers() {
+        let mut cassette = Cassette::default();
+        let mut authorized = request("GET", "https://api.example.com/me", None);
+        authorized.headers = vec![
+            (
+                "Authorization".to_string(),
+                "Bearer sk-live-123".to_string(),
+            ),
+            ("x-api-key".to_string(), "key-456".to_string()),
+        ];
+        cassette.record(&authorized, &response(b"{}"));
+
+        let json = serde_json::to_string(&cassette).unwrap();
+        assert!(!json.contains("sk-live-123"), "{json}");
+        assert!(!json.contains("key-456"), "{json}");
+        assert!(
+            !json.to_ascii_lowercase().contains("authorization"),
+            "{json}"
+        );
+
+        // cassettes recorded before headers were dropped still load
+        let old = r#"{"interactions":[{"request":{"method":"GET","url":"http://x/","headers":[["authorization","Bearer t"]]},"error":"down"}]}"#;
+        let mut replayer = Replayer::new(serde_json::from_str(old).unwrap());
+        assert!(replayer
+            .replay(&request("GET", "http://x/", None))
+            .is_some());
+    }
+
+    #[test]
+    fn round_trips_binary_bodies_and_errors() {
+        let mut cassette = Cassette::default();
+        let binary = request("POST", "http://x/bin", Some(&[0xff, 0x00]));
+        cassette.record(&binary, &response(&[0xde, 0xad, 0xbe, 0xef]));
+        let failing = request("GET", "http://x/down", None);
+        cassette.record(&failing, &Err("connection refused".to_string()));
+
+        let json = serde_json::to_string(&cassette).unwrap();
+        assert!(json.contains(r#""encoding":"hex","data":"deadbeef""#));
+        let mut replayer = Replayer::new(serde_json::from_str(&json).unwrap());
+        assert_eq!(body(replayer.replay(&binary)), [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            replayer.replay(&failing).unwrap().unwrap_err(),
+            "connection refused"
+        );
+    }
+}
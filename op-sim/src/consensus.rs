@@ -0,0 +1,376 @@
+// consensus
+// runs one component as several simulated operators and checks whether their outputs agree
+
+use crate::determinism::outcome_differences;
+use crate::sim::{GuestOutput, Simulation};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+/// How operator outputs are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    /// outputs must be identical strings
+    Bytes,
+    /// JSON outputs are compared after normalizing key order, whitespace and number formatting
+    Canonical,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Agreement {
+    /// number of operators in the largest group of agreeing outputs
+    pub largest_group: usize,
+    pub percentage: f64,
+    pub threshold_met: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OperatorReport {
+    pub operator: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// index of the group of byte-identical outputs this operator belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_group: Option<usize>,
+    /// index of the group of canonically equal outputs this operator belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_group: Option<usize>,
+    /// how this operator's outcome differs from the largest canonical group
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub differences: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsensusReport {
+    pub operators: usize,
+    pub threshold_percentage: f64,
+    pub comparison: Comparison,
+    pub bytes: Agreement,
+    pub canonical: Agreement,
+    /// whether the threshold is met under the chosen comparison
+    pub consensus: bool,
+    /// first operator of the largest canonical group, which the others are diffed against
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference_operator: Option<usize>,
+    pub results: Vec<OperatorReport>,
+}
+
+impl ConsensusReport {
+    pub fn print_human(&self) {
+        for result in &self.results {
+            match (&result.output, &result.error) {
+                (Some(output), _) => println!("operator {}: {output}", result.operator),
+                (None, Some(error)) => println!("operator {}: error: {error}", result.operator),
+                (None, None) => println!("operator {}: no output", result.operator),
+            }
+            if let (false, Some(reference)) =
+                (result.differences.is_empty(), self.reference_operator)
+            {
+                println!("  differs from operator {reference}:");
+                for difference in &result.differences {
+                    println!("    {difference}");
+                }
+            }
+        }
+        for (label, agreement) in [
+            ("byte-for-byte", &self.bytes),
+            ("canonical JSON", &self.canonical),
+        ] {
+            println!(
+                "{label}: {}/{} operators agree ({:.1}%), threshold {}",
+                agreement.largest_group,
+                self.operators,
+                agreement.percentage,
+                if agreement.threshold_met {
+                    "met"
+                } else {
+                    "not met"
+                }
+            );
+        }
+        println!(
+            "consensus ({} comparison, {}% threshold): {}",
+            match self.comparison {
+                Comparison::Bytes => "bytes",
+                Comparison::Canonical => "canonical",
+            },
+            self.threshold_percentage,
+            if self.consensus {
+                "reached"
+            } else {
+                "NOT reached"
+            }
+        );
+    }
+}
+
+/// Parses a `--quorum-threshold-percentage`, which must be from 0 to 100;
+/// above 100 could never be met and below 0 would always be.
+pub fn parse_threshold(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .parse()
+        .map_err(|e| format!("expected a number of percent: {e}"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("must be between 0 and 100, not {value}"));
+    }
+    Ok(percent)
+}
+
+/// Runs every operator's simulation on the same input concurrently and
+/// returns their outcomes in operator order.
+pub async fn run_operators(
+    operators: Vec<Simulation>,
+    input: &str,
+) -> Vec<anyhow::Result<Result<String, String>>> {
+    let mut tasks = JoinSet::new();
+    for (operator, simulation) in operators.into_iter().enumerate() {
+        let input: Arc<str> = input.into();
+        tasks.spawn(async move {
+            let outcome = match simulation.run(&input, &GuestOutput::capture()).await {
+                Ok(run) => run.outcome,
+                Err(e) => Err(e),
+            };
+            (operator, outcome)
+        });
+    }
+
+    let mut outcomes = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        outcomes.push(result.expect("operator task panicked"));
+    }
+    outcomes.sort_by_key(|(operator, _)| *operator);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// Groups operator outcomes by agreement. Only successful outputs can agree;
+/// an operator whose guest or host failed counts against consensus.
+pub fn evaluate(
+    outcomes: &[anyhow::Result<Result<String, String>>],
+    threshold_percentage: f64,
+    comparison: Comparison,
+) -> ConsensusReport {
+    let outputs: Vec<Option<&str>> = outcomes
+        .iter()
+        .map(|outcome| match outcome {
+            Ok(Ok(output)) => Some(output.as_str()),
+            _ => None,
+        })
+        .collect();
+    let canonical: Vec<Option<String>> = outputs
+        .iter()
+        .map(|output| output.map(canonicalize))
+        .collect();
+
+    let (bytes_groups, bytes_largest) = group(&outputs);
+    let (canonical_groups, canonical_largest) = group(&canonical);
+    let agreement = |largest: Option<(usize, usize)>| {
+        let size = largest.map_or(0, |(_, size)| size);
+        let percentage = if outcomes.is_empty() {
+            0.0
+        } else {
+            size as f64 * 100.0 / outcomes.len() as f64
+        };
+        Agreement {
+            largest_group: size,
+            percentage,
+            threshold_met: size > 0 && percentage >= threshold_percentage,
+        }
+    };
+    let bytes = agreement(bytes_largest);
+    let canonical_agreement = agreement(canonical_largest);
+
+    // operators outside the largest canonical group are diffed against its first member
+    let reference_operator = canonical_largest
+        .and_then(|(group, _)| canonical_groups.iter().position(|g| *g == Some(group)));
+    let reference = reference_operator.map(|operator| &outcomes[operator]);
+    let results = outcomes
+        .iter()
+        .enumerate()
+        .map(|(operator, outcome)| {
+            let (output, error) = match outcome {
+                Ok(Ok(output)) => (Some(output.clone()), None),
+                Ok(Err(error)) => (None, Some(error.clone())),
+                Err(error) => (None, Some(format!("{error:#}"))),
+            };
+            let differences = match (reference, canonical_largest) {
+                (Some(reference), Some((group, _)))
+                    if canonical_groups[operator] != Some(group) =>
+                {
+                    outcome_differences(reference, outcome)
+                }
+                _ => Vec::new(),
+            };
+            OperatorReport {
+                operator,
+                output,
+                error,
+                bytes_group: bytes_groups[operator],
+                canonical_group: canonical_groups[operator],
+                differences,
+            }
+        })
+        .collect();
+
+    let consensus = match comparison {
+        Comparison::Bytes => bytes.threshold_met,
+        Comparison::Canonical => canonical_agreement.threshold_met,
+    };
+    ConsensusReport {
+        operators: outcomes.len(),
+        threshold_percentage,
+        comparison,
+        bytes,
+        canonical: canonical_agreement,
+        consensus,
+        reference_operator,
+        results,
+    }
+}
+
+/// Assigns each value the index of its group of equal values, in order of
+/// first appearance, and returns the largest group as (index, size).
+fn group<T: PartialEq>(values: &[Option<T>]) -> (Vec<Option<usize>>, Option<(usize, usize)>) {
+    let mut representatives: Vec<&T> = Vec::new();
+    let mut sizes: Vec<usize> = Vec::new();
+    let groups = values
+        .iter()
+        .map(|value| {
+            let value = value.as_ref()?;
+            let index = match representatives.iter().position(|r| *r == value) {
+                Some(index) => index,
+                None => {
+                    representatives.push(value);
+                    sizes.push(0);
+                    sizes.len() - 1
+                }
+            };
+            sizes[index] += 1;
+            Some(index)
+        })
+        .collect();
+    // ties go to the group that appeared first
+    let largest =
+        sizes
+            .iter()
+            .enumerate()
+            .fold(
+                None,
+                |best: Option<(usize, usize)>, (index, &size)| match best {
+                    Some((_, best_size)) if best_size >= size => best,
+                    _ => Some((index, size)),
+                },
+            );
+    (groups, largest)
+}
+
+/// Canonical form of an output: JSON with sorted keys, no whitespace and
+/// integral floats written as integers; anything else as-is.
+pub fn canonicalize(output: &str) -> String {
+    match serde_json::from_str::<Value>(output) {
+        Ok(mut value) => {
+            normalize(&mut value);
+            value.to_string()
+        }
+        Err(_) => output.to_string(),
+    }
+}
+
+fn normalize(value: &mut Value) {
+    match value {
+        Value::Number(number) => {
+            if let Some(float) = number.as_f64().filter(|_| number.is_f64()) {
+                if float.fract() == 0.0 && float.abs() < i64::MAX as f64 {
+                    *number = (float as i64).into();
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(normalize),
+        Value::Object(fields) => {
+            // a no-op unless serde_json's `preserve_order` is enabled somewhere in the build
+            fields.sort_keys();
+            fields.values_mut().for_each(normalize);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(output: &str) -> anyhow::Result<Result<String, String>> {
+        Ok(Ok(output.to_string()))
+    }
+
+    #[test]
+    fn thresholds_must_be_percentages() {
+        assert_eq!(parse_threshold("0"), Ok(0.0));
+        assert_eq!(parse_threshold("66.7"), Ok(66.7));
+        assert_eq!(parse_threshold("100"), Ok(100.0));
+        for value in ["-1", "100.5", "NaN", "inf", "two thirds"] {
+            assert!(parse_threshold(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn canonicalizes_key_order_whitespace_and_integral_floats() {
+        assert_eq!(
+            canonicalize(r#"{ "b": [1.0, 2.5], "a": {"d": 1, "c": -3.0} }"#),
+            r#"{"a":{"c":-3,"d":1},"b":[1,2.5]}"#
+        );
+        assert_eq!(canonicalize("not json "), "not json ");
+    }
+
+    #[test]
+    fn groups_in_order_of_first_appearance() {
+        let values = [Some("a"), None, Some("b"), Some("b"), Some("a")];
+        let (groups, largest) = group(&values);
+        assert_eq!(groups, [Some(0), None, Some(1), Some(1), Some(0)]);
+        // ties go to the group that appeared first
+        assert_eq!(largest, Some((0, 2)));
+        assert_eq!(group::<&str>(&[None, None]), (vec![None, None], None));
+    }
+
+    #[test]
+    fn canonical_agreement_can_exceed_byte_agreement() {
+        let outcomes = [
+            ok(r#"{"price":1,"ok":true}"#),
+            ok(r#"{"ok": true, "price": 1.0}"#),
+            ok(r#"{"price":2,"ok":true}"#),
+        ];
+        let report = evaluate(&outcomes, 60.0, Comparison::Canonical);
+        assert_eq!(report.bytes.largest_group, 1);
+        assert!(!report.bytes.threshold_met);
+        assert_eq!(report.canonical.largest_group, 2);
+        assert!(report.canonical.threshold_met);
+        assert!(report.consensus);
+        assert_eq!(report.reference_operator, Some(0));
+        assert!(report.results[1].differences.is_empty());
+        assert!(!report.results[2].differences.is_empty());
+
+        assert!(!evaluate(&outcomes, 60.0, Comparison::Bytes).consensus);
+    }
+
+    #[test]
+    fn failures_count_against_consensus() {
+        let outcomes = [
+            ok("{}"),
+            Ok(Err("guest error".to_string())),
+            Err(anyhow::anyhow!("trap")),
+        ];
+        let report = evaluate(&outcomes, 50.0, Comparison::Canonical);
+        assert_eq!(report.canonical.largest_group, 1);
+        assert!(!report.consensus);
+        assert_eq!(report.results[1].error.as_deref(), Some("guest error"));
+        assert_eq!(report.results[2].canonical_group, None);
+
+        let failed = [Ok(Err("a".to_string())), Ok(Err("a".to_string()))];
+        let report = evaluate(&failed, 0.0, Comparison::Canonical);
+        assert!(!report.consensus);
+        assert_eq!(report.reference_operator, None);
+    }
+}
@@ -0,0 +1,257 @@
+// determinism
+// frozen clocks and seeded randomness for reproducible runs, and comparison of repeated runs
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde_json::Value;
+use std::time::Duration;
+use wasmtime_wasi::p2::WasiCtxBuilder;
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct DeterminismArgs {
+    /// Pin the guest's wall clock to this time, as RFC 3339 (2025-01-01T00:00:00Z) or Unix seconds
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub frozen_time: Option<Duration>,
+    /// Seed the guest's random sources instead of using OS randomness
+    #[arg(long, value_name = "SEED")]
+    pub random_seed: Option<u64>,
+    /// Shorthand for --frozen-time 1970-01-01T00:00:00Z --random-seed 0, unless those are given
+    #[arg(long)]
+    pub deterministic: bool,
+}
+
+/// Clock and randomness overrides applied to every run. `None` leaves the
+/// guest with the host's real clock or randomness.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Determinism {
+    /// wall-clock time since the Unix epoch
+    pub frozen_time: Option<Duration>,
+    pub random_seed: Option<u64>,
+}
+
+impl Determinism {
+    pub fn from_args(args: &DeterminismArgs) -> Self {
+        Self {
+            frozen_time: args
+                .frozen_time
+                .or(args.deterministic.then_some(Duration::ZERO)),
+            random_seed: args.random_seed.or(args.deterministic.then_some(0)),
+        }
+    }
+
+    pub fn configure_wasi(&self, builder: &mut WasiCtxBuilder) {
+        if let Some(time) = self.frozen_time {
+            builder.wall_clock(FrozenWallClock(time));
+            builder.monotonic_clock(FrozenMonotonicClock);
+        }
+        if let Some(seed) = self.random_seed {
+            // distinct streams for the secure and insecure sources, all derived from the one seed
+            builder.secure_random(StdRng::seed_from_u64(seed));
+            builder.insecure_random(StdRng::seed_from_u64(seed.wrapping_add(1)));
+            builder.insecure_random_seed(u128::from(seed));
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Result<Duration, String> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(value)
+        .map_err(|e| format!("expected RFC 3339 time or Unix seconds: {e}"))?;
+    let nanos = time
+        .timestamp_nanos_opt()
+        .and_then(|nanos| u64::try_from(nanos).ok())
+        .ok_or("time must be between 1970 and 2262")?;
+    Ok(Duration::from_nanos(nanos))
+}
+
+struct FrozenWallClock(Duration);
+
+impl HostWallClock for FrozenWallClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        self.0
+    }
+}
+
+// guests only see differences between monotonic readings, so a frozen one can start at zero
+struct FrozenMonotonicClock;
+
+impl HostMonotonicClock for FrozenMonotonicClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// Describes how two run outcomes differ, one line per difference. Outputs
+/// are compared byte for byte, as operators compare them; when both are JSON,
+/// a mismatch is explained value by value with the path of each difference,
+/// or as a byte-level difference (key order, whitespace, number formatting)
+/// when the values are equal. Anything else is compared as a whole.
+pub fn outcome_differences(
+    expected: &anyhow::Result<Result<String, String>>,
+    actual: &anyhow::Result<Result<String, String>>,
+) -> Vec<String> {
+    if let (Ok(Ok(expected)), Ok(Ok(actual))) = (expected, actual) {
+        if expected == actual {
+            return Vec::new();
+        }
+        if let (Ok(expected_value), Ok(actual_value)) = (
+            serde_json::from_str::<Value>(expected),
+            serde_json::from_str::<Value>(actual),
+        ) {
+            let mut differences = Vec::new();
+            json_differences("$", &expected_value, &actual_value, &mut differences);
+            if differences.is_empty() {
+                differences.push(byte_difference(expected, actual));
+            }
+            return differences;
+        }
+    }
+    let (expected, actual) = (describe_outcome(expected), describe_outcome(actual));
+    if expected == actual {
+        Vec::new()
+    } else {
+        vec![format!("{expected} != {actual}")]
+    }
+}
+
+// where two outputs that hold the same JSON value first differ
+fn byte_difference(expected: &str, actual: &str) -> String {
+    let offset = expected
+        .bytes()
+        .zip(actual.bytes())
+        .position(|(a, b)| a != b)
+        .unwrap_or(expected.len().min(actual.len()));
+    let context = |output: &str| {
+        let mut start = offset.saturating_sub(16).min(output.len());
+        while !output.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = (offset + 16).min(output.len());
+        while !output.is_char_boundary(end) {
+            end += 1;
+        }
+        format!("{:?}", &output[start..end])
+    };
+    format!(
+        "$: same JSON value, but the bytes differ at offset {offset} (key order, whitespace or number formatting): {} != {}",
+        context(expected),
+        context(actual)
+    )
+}
+
+pub fn describe_outcome(outcome: &anyhow::Result<Result<String, String>>) -> String {
+    match outcome {
+        Ok(Ok(output)) => format!("output {output:?}"),
+        Ok(Err(error)) => format!("guest error {error:?}"),
+        Err(error) => format!("host error {:?}", format!("{error:#}")),
+    }
+}
+
+pub fn json_differences(
+    path: &str,
+    expected: &Value,
+    actual: &Value,
+    differences: &mut Vec<String>,
+) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            let mut keys: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{path}.{key}");
+                match (expected.get(key), actual.get(key)) {
+                    (Some(expected), Some(actual)) => {
+                        json_differences(&path, expected, actual, differences)
+                    }
+                    (Some(expected), None) => {
+                        differences.push(format!("{path}: {expected} != (missing)"))
+                    }
+                    (None, Some(actual)) => {
+                        differences.push(format!("{path}: (missing) != {actual}"))
+                    }
+                    (None, None) => unreachable!("key comes from one of the objects"),
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => {
+            for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                json_differences(&format!("{path}[{index}]"), expected, actual, differences);
+            }
+        }
+        _ if expected != actual => differences.push(format!("{path}: {expected} != {actual}")),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(output: &str) -> anyhow::Result<Result<String, String>> {
+        Ok(Ok(output.to_string()))
+    }
+
+    #[test]
+    fn identical_outputs_do_not_differ() {
+        let run = output(r#"{"price":1.5}"#);
+        assert!(outcome_differences(&run, &output(r#"{"price":1.5}"#)).is_empty());
+    }
+
+    #[test]
+    fn value_differences_are_reported_by_path() {
+        let differences = outcome_differences(
+            &output(r#"{"price":1.5,"ts":[1,2]}"#),
+            &output(r#"{"price":1.5,"ts":[1,3]}"#),
+        );
+        assert_eq!(differences, vec!["$.ts[1]: 2 != 3"]);
+    }
+
+    #[test]
+    fn byte_level_differences_are_nondeterminism() {
+        for (expected, actual) in [
+            (r#"{"a":1,"b":2}"#, r#"{"b":2,"a":1}"#),
+            (r#"{"a":1}"#, r#"{"a": 1}"#),
+            (r#"{"a":1.0}"#, r#"{"a":1.00}"#),
+        ] {
+            let differences = outcome_differences(&output(expected), &output(actual));
+            assert_eq!(differences.len(), 1, "{expected} vs {actual}");
+            assert!(
+                differences[0].contains("bytes differ"),
+                "{}",
+                differences[0]
+            );
+        }
+    }
+
+    #[test]
+    fn byte_context_stays_on_char_boundaries() {
+        let expected = r#"{"name":"€€€€€€€€€€","a":1,"b":2}"#;
+        let actual = r#"{"name":"€€€€€€€€€€","b":2,"a":1}"#;
+        let differences = outcome_differences(&output(expected), &output(actual));
+        assert!(
+            differences[0].contains(
+                r#"offset 42 (key order, whitespace or number formatting): "€€€€€\",\"a\":1"#
+            ),
+            "{}",
+            differences[0]
+        );
+    }
+
+    #[test]
+    fn non_json_outcomes_are_compared_whole() {
+        let differences = outcome_differences(&output("up"), &Ok(Err("down".to_string())));
+        assert_eq!(differences, vec![r#"output "up" != guest error "down""#]);
+    }
+}
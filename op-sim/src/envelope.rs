@@ -0,0 +1,136 @@
+// envelope
+// structured JSON result of an op-sim run, keeping guest stdio apart from the result
+
+use crate::faults::AppliedFault;
+use crate::limits::LimitExceeded;
+use crate::log::LogEntry;
+use crate::metrics::millis;
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The guest's `run` returned an error
+    Guest,
+    /// op-sim could not load, instantiate or finish running the guest
+    Host,
+    /// The guest ran out of fuel
+    Fuel,
+    /// The run went past its wall-clock deadline
+    Deadline,
+    /// The guest hit a memory, table or instance limit
+    ResourceLimit,
+}
+
+impl ErrorKind {
+    fn of_host_error(error: &anyhow::Error) -> Self {
+        match LimitExceeded::find(error) {
+            Some(LimitExceeded::Fuel { .. }) => ErrorKind::Fuel,
+            Some(LimitExceeded::Deadline { .. }) => ErrorKind::Deadline,
+            Some(_) => ErrorKind::ResourceLimit,
+            None => ErrorKind::Host,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Timings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instantiate_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_ms: Option<f64>,
+    pub wall_time_ms: f64,
+}
+
+impl Timings {
+    pub fn set_compile(&mut self, duration: Duration) {
+        self.compile_ms = Some(millis(duration));
+    }
+
+    pub fn set_instantiate(&mut self, duration: Duration) {
+        self.instantiate_ms = Some(millis(duration));
+    }
+
+    pub fn set_run(&mut self, duration: Duration) {
+        self.run_ms = Some(millis(duration));
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Envelope {
+    pub status: Status,
+    /// Guest output, parsed as JSON when possible and kept as a string otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ErrorKind>,
+    pub guest_stdout: String,
+    pub guest_stderr: String,
+    pub timings: Timings,
+    /// Faults injected into the guest's fetches
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub faults: Vec<AppliedFault>,
+    /// Entries the guest logged through newton:provider/log
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<LogEntry>,
+    /// Entries logged past the collection limit
+    #[serde(skip_serializing_if = "is_zero")]
+    pub logs_dropped: usize,
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+impl Envelope {
+    pub fn new(
+        outcome: &anyhow::Result<Result<String, String>>,
+        guest_stdout: &[u8],
+        guest_stderr: &[u8],
+        timings: Timings,
+    ) -> Self {
+        let (status, output, error, error_kind) = match outcome {
+            Ok(Ok(output)) => (Status::Ok, Some(parse_output(output)), None, None),
+            Ok(Err(error)) => (
+                Status::Error,
+                None,
+                Some(error.clone()),
+                Some(ErrorKind::Guest),
+            ),
+            Err(error) => (
+                Status::Error,
+                None,
+                Some(format!("{error:#}")),
+                Some(ErrorKind::of_host_error(error)),
+            ),
+        };
+        Self {
+            status,
+            output,
+            error,
+            error_kind,
+            guest_stdout: String::from_utf8_lossy(guest_stdout).into_owned(),
+            guest_stderr: String::from_utf8_lossy(guest_stderr).into_owned(),
+            timings,
+            faults: Vec::new(),
+            logs: Vec::new(),
+            logs_dropped: 0,
+        }
+    }
+}
+
+fn parse_output(output: &str) -> serde_json::Value {
+    serde_json::from_str(output).unwrap_or_else(|_| serde_json::Value::String(output.to_string()))
+}
@@ -0,0 +1,447 @@
+// faults
+// injects latency, connection errors and bad responses into guest fetches from a declarative rule file
+
+use crate::mock::UrlMatcher;
+use crate::newton::provider::http::{HttpRequest, HttpResponse};
+use crate::trace::Redactor;
+use anyhow::{bail, Context};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// body served by `non_json_body`, like the error page of a misbehaving proxy
+const NON_JSON_BODY: &str = "<html><body><h1>502 Bad Gateway</h1></body></html>";
+
+/// On-disk shape of a faults file, in TOML or JSON.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaultFile {
+    /// Seed for rules with a `probability`
+    seed: Option<u64>,
+    #[serde(default, alias = "faults")]
+    fault: Vec<FaultSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaultSpec {
+    /// HTTP method to match; any method when omitted
+    method: Option<String>,
+    /// URL glob pattern
+    url: Option<String>,
+    /// URL regular expression, used instead of `url`
+    url_regex: Option<String>,
+    /// Chance that the fault applies to a matching fetch, from 0 to 1
+    #[serde(default = "default_probability")]
+    probability: f64,
+    /// Delay the fetch by this many milliseconds
+    latency_ms: Option<u64>,
+    /// Fail the fetch with this error, as if the connection failed
+    error: Option<String>,
+    /// Replace the response status
+    status: Option<u16>,
+    /// Replace the response body
+    body: Option<String>,
+    /// Keep only this fraction (0 to 1) of the response body
+    truncate_body: Option<f64>,
+    /// Replace the response body with an HTML error page
+    #[serde(default)]
+    non_json_body: bool,
+}
+
+fn default_probability() -> f64 {
+    1.0
+}
+
+struct FaultRule {
+    method: Option<String>,
+    url: UrlMatcher,
+    probability: f64,
+    latency: Option<Duration>,
+    error: Option<String>,
+    status: Option<u16>,
+    body: Option<String>,
+    truncate_body: Option<f64>,
+    non_json_body: bool,
+}
+
+impl FaultRule {
+    fn from_spec(spec: FaultSpec) -> anyhow::Result<Self> {
+        if !(0.0..=1.0).contains(&spec.probability) {
+            bail!("`probability` must be between 0 and 1");
+        }
+        if spec
+            .truncate_body
+            .is_some_and(|fraction| !(0.0..=1.0).contains(&fraction))
+        {
+            bail!("`truncate_body` must be between 0 and 1");
+        }
+        let body_faults = [
+            spec.body.is_some(),
+            spec.truncate_body.is_some(),
+            spec.non_json_body,
+        ];
+        if body_faults.iter().filter(|set| **set).count() > 1 {
+            bail!("only one of `body`, `truncate_body` and `non_json_body` may be set");
+        }
+        if spec.error.is_some() && (spec.status.is_some() || body_faults.contains(&true)) {
+            bail!("`error` cannot be combined with response faults");
+        }
+        Ok(Self {
+            method: spec.method,
+            url: UrlMatcher::new(spec.url, spec.url_regex)?,
+            probability: spec.probability,
+            latency: spec.latency_ms.map(Duration::from_millis),
+            error: spec.error,
+            status: spec.status,
+            body: spec.body,
+            truncate_body: spec.truncate_body,
+            non_json_body: spec.non_json_body,
+        })
+    }
+
+    fn matches(&self, request: &HttpRequest) -> bool {
+        self.method
+            .as_ref()
+            .is_none_or(|method| method.eq_ignore_ascii_case(&request.method))
+            && self.url.is_match(&request.url)
+    }
+
+    fn apply_to_response(&self, response: &mut HttpResponse, applied: &mut Vec<String>) {
+        if let Some(status) = self.status {
+            response.status = status;
+            applied.push(format!("status {status}"));
+        }
+        if let Some(body) = &self.body {
+            response.body = body.clone().into_bytes();
+            applied.push("replaced body".to_string());
+        }
+        if let Some(fraction) = self.truncate_body {
+            let keep = (response.body.len() as f64 * fraction) as usize;
+            applied.push(format!(
+                "truncated body from {} to {keep} bytes",
+                response.body.len()
+            ));
+            response.body.truncate(keep);
+        }
+        if self.non_json_body {
+            response.body = NON_JSON_BODY.as_bytes().to_vec();
+            response
+                .headers
+                .retain(|(name, _)| !name.eq_ignore_ascii_case("content-type"));
+            response
+                .headers
+                .push(("content-type".to_string(), "text/html".to_string()));
+            applied.push("non-JSON body".to_string());
+        }
+    }
+}
+
+/// An ordered set of fault rules. The first matching rule that fires (given
+/// its probability) applies to a fetch.
+pub struct FaultRules {
+    rules: Vec<FaultRule>,
+    seed: u64,
+}
+
+impl FaultRules {
+    /// Loads a faults file. Files ending in `.json` are parsed as JSON,
+    /// anything else as TOML.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read faults file {}", path.display()))?;
+        let file: FaultFile = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse faults file {}", path.display()))?
+        } else {
+            toml::from_str(&contents)
+                .with_context(|| format!("failed to parse faults file {}", path.display()))?
+        };
+
+        Self::from_file(file).with_context(|| format!("invalid faults file {}", path.display()))
+    }
+
+    fn from_file(file: FaultFile) -> anyhow::Result<Self> {
+        let rules = file
+            .fault
+            .into_iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                FaultRule::from_spec(spec).with_context(|| format!("invalid fault #{}", i + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            rules,
+            seed: file.seed.unwrap_or_default(),
+        })
+    }
+
+    /// Overrides the seed from the file.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// A fault that was applied to one fetch.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedFault {
+    pub method: String,
+    pub url: String,
+    /// 1-based index of the rule in the faults file
+    pub rule: usize,
+    pub faults: Vec<String>,
+}
+
+/// Prints the faults applied during a run to stderr.
+pub fn print_summary(applied: &[AppliedFault]) {
+    eprintln!("Injected {} fault(s):", applied.len());
+    for fault in applied {
+        eprintln!(
+            "  {} {} (rule {}): {}",
+            fault.method,
+            fault.url,
+            fault.rule,
+            fault.faults.join(", ")
+        );
+    }
+}
+
+/// Per-run fault state: the random source for probabilities and the faults
+/// applied so far.
+pub struct FaultInjector {
+    rules: Arc<FaultRules>,
+    rng: StdRng,
+    pub applied: Vec<AppliedFault>,
+}
+
+impl FaultInjector {
+    pub fn new(rules: Arc<FaultRules>) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(rules.seed),
+            rules,
+            applied: Vec::new(),
+        }
+    }
+
+    /// Picks the rule that applies to a request, if any.
+    pub fn select(&mut self, request: &HttpRequest) -> Option<usize> {
+        let rng = &mut self.rng;
+        self.rules.rules.iter().position(|rule| {
+            rule.matches(request) && (rule.probability >= 1.0 || rng.gen_bool(rule.probability))
+        })
+    }
+
+    /// Redacts credentials from the URLs of the faults applied so far, as
+    /// HTTP traces do, before they are reported.
+    pub fn redact_urls<'a>(&mut self, secrets: impl IntoIterator<Item = &'a str>) {
+        let redactor = Redactor::new(secrets);
+        for fault in &mut self.applied {
+            fault.url = redactor.url(&fault.url);
+        }
+    }
+
+    /// Applies the selected rule around `fetch`: waits out any latency, fails
+    /// with the rule's error without fetching, or fetches and then alters the
+    /// response.
+    pub async fn inject<F>(
+        &mut self,
+        index: usize,
+        request: HttpRequest,
+        fetch: F,
+    ) -> Result<HttpResponse, String>
+    where
+        F: std::future::Future<Output = Result<HttpResponse, String>>,
+    {
+        let rule = &self.rules.rules[index];
+        let mut applied = AppliedFault {
+            method: request.method.clone(),
+            url: request.url.clone(),
+            rule: index + 1,
+            faults: Vec::new(),
+        };
+
+        if let Some(latency) = rule.latency {
+            tokio::time::sleep(latency).await;
+            applied
+                .faults
+                .push(format!("latency {} ms", latency.as_millis()));
+        }
+        let result = match &rule.error {
+            Some(error) => {
+                applied.faults.push(format!("error {error:?}"));
+                Err(error.clone())
+            }
+            None => fetch.await.map(|mut response| {
+                rule.apply_to_response(&mut response, &mut applied.faults);
+                response
+            }),
+        };
+
+        self.applied.push(applied);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(toml: &str) -> Arc<FaultRules> {
+        Arc::new(FaultRules::from_file(toml::from_str(toml).unwrap()).unwrap())
+    }
+
+    fn request(method: &str, url: &str) -> HttpRequest {
+        HttpRequest {
+            url: url.to_string(),
+            method: method.to_string(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    fn response(body: &str) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn selects_the_first_matching_rule() {
+        let mut injector = FaultInjector::new(rules(
+            r#"
+            [[fault]]
+            method = "POST"
+            url = "https://rpc.example.com/*"
+            error = "connection reset"
+
+            [[fault]]
+            url_regex = "^https://api\\.example\\.com/"
+            status = 503
+            "#,
+        ));
+        assert_eq!(
+            injector.select(&request("post", "https://rpc.example.com/v1")),
+            Some(0)
+        );
+        assert_eq!(
+            injector.select(&request("GET", "https://rpc.example.com/v1")),
+            None
+        );
+        assert_eq!(
+            injector.select(&request("GET", "https://api.example.com/x")),
+            Some(1)
+        );
+        assert_eq!(
+            injector.select(&request("GET", "https://other.example.com/")),
+            None
+        );
+    }
+
+    #[test]
+    fn probabilities_follow_the_seed() {
+        let faults = rules(
+            r#"
+            seed = 7
+
+            [[fault]]
+            probability = 0.5
+            status = 500
+            "#,
+        );
+        let picks = |rules: Arc<FaultRules>| {
+            let mut injector = FaultInjector::new(rules);
+            (0..64)
+                .map(|_| injector.select(&request("GET", "http://x/")).is_some())
+                .collect::<Vec<_>>()
+        };
+        let first = picks(faults.clone());
+        assert_eq!(first, picks(faults));
+        assert!(first.contains(&true) && first.contains(&false));
+
+        let never = rules("[[fault]]\nprobability = 0.0\nstatus = 500\n");
+        let mut injector = FaultInjector::new(never);
+        assert!((0..64).all(|_| injector.select(&request("GET", "http://x/")).is_none()));
+    }
+
+    #[tokio::test]
+    async fn injects_errors_without_fetching() {
+        let mut injector = FaultInjector::new(rules("[[fault]]\nerror = \"reset\"\n"));
+        let result = injector
+            .inject(0, request("GET", "http://x/"), async {
+                panic!("the fetch should not run")
+            })
+            .await;
+        assert_eq!(result.unwrap_err(), "reset");
+        assert_eq!(injector.applied[0].rule, 1);
+        assert_eq!(injector.applied[0].faults, ["error \"reset\""]);
+    }
+
+    #[tokio::test]
+    async fn alters_fetched_responses() {
+        let mut injector = FaultInjector::new(rules(
+            r#"
+            [[fault]]
+            status = 502
+            truncate_body = 0.5
+
+            [[fault]]
+            non_json_body = true
+            "#,
+        ));
+        let truncated = injector
+            .inject(0, request("GET", "http://x/"), async {
+                Ok(response("0123456789"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(truncated.status, 502);
+        assert_eq!(truncated.body, b"01234");
+
+        let html = injector
+            .inject(1, request("GET", "http://x/"), async { Ok(response("{}")) })
+            .await
+            .unwrap();
+        assert_eq!(html.body, NON_JSON_BODY.as_bytes());
+        assert_eq!(
+            html.headers,
+            [("content-type".to_string(), "text/html".to_string())]
+        );
+        assert_eq!(injector.applied.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn redacts_credentials_from_applied_fault_urls() {
+        let mut injector = FaultInjector::new(rules("[[fault]]\nstatus = 500\n"));
+        let url = "https://api.example.com/v1/price?symbol=ETH&api_key=k-123&sig=a1b2c3d4";
+        injector
+            .inject(0, request("GET", url), async { Ok(response("{}")) })
+            .await
+            .unwrap();
+        injector.redact_urls(["a1b2c3d4"]);
+        assert_eq!(
+            injector.applied[0].url,
+            "https://api.example.com/v1/price?symbol=ETH&api_key=[REDACTED]&sig=[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rule in [
+            "probability = 1.5",
+            "truncate_body = -0.1",
+            "body = \"x\"\nnon_json_body = true",
+            "error = \"x\"\nstatus = 500",
+            "url = \"*\"\nurl_regex = \".*\"",
+        ] {
+            let file = toml::from_str(&format!("[[fault]]\n{rule}\n")).unwrap();
+            assert!(FaultRules::from_file(file).is_err(), "{rule}");
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// http
+// the newton:provider/http host: live, recorded, replayed or mocked fetches
+
+use crate::cassette::{Cassette, Replayer};
+use crate::faults::{AppliedFault, FaultInjector, FaultRules};
+use crate::mock::MockRules;
+use crate::network::NetworkPolicy;
+use crate::newton::provider::http::{HttpRequest, HttpResponse};
+use crate::trace::HttpTrace;
+use crate::variance::NumericVariance;
+use rand::rngs::StdRng;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A fetch handler supplied by an embedder, for answering guest fetches from
+/// something other than the network, a cassette or mock rules. It is shared
+/// by every run, so any per-run state needs interior mutability.
+///
+/// Requests reach the handler only after passing the network policy.
+pub trait HttpHost: Send + Sync {
+    fn fetch(
+        &self,
+        request: HttpRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<HttpResponse, String>> + Send + '_>>;
+}
+
+/// Where guest fetches are answered from. Shared by every run of a
+/// simulation; each run gets a fresh [`HttpMode`] from it.
+#[derive(Clone)]
+pub enum HttpSource {
+    Live,
+    Record,
+    Replay(Cassette),
+    Mock(Arc<MockRules>),
+    Custom(Arc<dyn HttpHost>),
+}
+
+impl HttpSource {
+    pub fn mode(&self) -> HttpMode {
+        match self {
+            HttpSource::Live => HttpMode::Live,
+            HttpSource::Record => HttpMode::Record(Cassette::default()),
+            HttpSource::Replay(cassette) => HttpMode::Replay(Replayer::new(cassette.clone())),
+            HttpSource::Mock(rules) => HttpMode::Mock(Arc::clone(rules)),
+            HttpSource::Custom(host) => HttpMode::Custom(Arc::clone(host)),
+        }
+    }
+}
+
+pub enum HttpMode {
+    Live,
+    Record(Cassette),
+    Replay(Replayer),
+    Mock(Arc<MockRules>),
+    Custom(Arc<dyn HttpHost>),
+}
+
+pub struct HttpProvider {
+    pub mode: HttpMode,
+    network: Arc<NetworkPolicy>,
+    client: reqwest::Client,
+    run_started: Instant,
+    // requests that could not be served in replay or mock mode
+    pub unmatched: Vec<String>,
+    // latency of every fetch, in call order
+    pub fetch_latencies: Vec<Duration>,
+    variance: Option<(NumericVariance, StdRng)>,
+    pub faults: Option<FaultInjector>,
+    pub trace: Option<HttpTrace>,
+}
+
+impl HttpProvider {
+    pub fn new(mode: HttpMode, network: Arc<NetworkPolicy>) -> anyhow::Result<Self> {
+        Ok(Self {
+            mode,
+            client: network.client()?,
+            network,
+            run_started: Instant::now(),
+            unmatched: Vec::new(),
+            fetch_latencies: Vec::new(),
+            variance: None,
+            faults: None,
+            trace: None,
+        })
+    }
+
+    pub fn with_variance(mut self, variance: Option<NumericVariance>) -> Self {
+        self.variance = variance.map(|variance| (variance, variance.rng()));
+        self
+    }
+
+    pub fn with_faults(mut self, faults: Option<Arc<FaultRules>>) -> Self {
+        self.faults = faults.map(FaultInjector::new);
+        self
+    }
+
+    pub fn with_trace(mut self, enabled: bool) -> Self {
+        self.trace = enabled.then(|| HttpTrace::new(self.run_started));
+        self
+    }
+
+    /// When the run started, which the total HTTP time budget counts from.
+    pub fn run_started(&self) -> Instant {
+        self.run_started
+    }
+
+    pub fn applied_faults(&self) -> &[AppliedFault] {
+        self.faults.as_ref().map_or(&[], |faults| &faults.applied)
+    }
+
+    async fn dispatch(&mut self, request: HttpRequest) -> Result<HttpResponse, String> {
+        let url = reqwest::Url::parse(&request.url)
+            .map_err(|e| format!("invalid URL `{}`: {e}", request.url))?;
+        self.network.check_url(&url)?;
+
+        match &mut self.mode {
+            HttpMode::Live => {
+                fetch_live(&self.client, &self.network, self.run_started, request).await
+            }
+            HttpMode::Record(cassette) => {
+                let result = fetch_live(
+                    &self.client,
+                    &self.network,
+                    self.run_started,
+                    request.clone(),
+                )
+                .await;
+                cassette.record(&request, &result);
+                result
+            }
+            HttpMode::Replay(replayer) => match replayer.replay(&request) {
+                Some(result) => result,
+                None => Err(self.record_unmatched(&request, "no recorded interaction")),
+            },
+            HttpMode::Mock(rules) => match rules.respond(&request) {
+                Some(result) => result,
+                None => Err(self.record_unmatched(&request, "no mock rule")),
+            },
+            HttpMode::Custom(host) => host.fetch(request).await,
+        }
+    }
+
+    fn record_unmatched(&mut self, request: &HttpRequest, reason: &str) -> String {
+        let description = format!("{} {}", request.method, request.url);
+        let error = format!("{reason} for {description}");
+        self.unmatched.push(description);
+        error
+    }
+}
+
+// custom provider
+impl crate::newton::provider::http::Host for HttpProvider {
+    async fn fetch(&mut self, request: HttpRequest) -> Result<HttpResponse, String> {
+        let started = Instant::now();
+        // the injector is taken out for the fetch so that it can wrap `dispatch`
+        let mut faults = self.faults.take();
+        let traced = self.trace.is_some().then(|| request.clone());
+        let mut result = match faults
+            .as_mut()
+            .and_then(|faults| Some((faults.select(&request)?, faults)))
+        {
+            Some((rule, faults)) => {
+                faults
+                    .inject(rule, request.clone(), self.dispatch(request))
+                    .await
+            }
+            None => self.dispatch(request).await,
+        };
+        self.faults = faults;
+        self.fetch_latencies.push(started.elapsed());
+        if let (Ok(response), Some((variance, rng))) = (&mut result, &mut self.variance) {
+            response.body = variance.apply(rng, std::mem::take(&mut response.body));
+        }
+        if let (Some(trace), Some(request)) = (&mut self.trace, traced) {
+            trace.record(started, request, &result);
+        }
+        result
+    }
+}
+
+async fn fetch_live(
+    client: &reqwest::Client,
+    network: &NetworkPolicy,
+    run_started: Instant,
+    request: HttpRequest,
+) -> Result<HttpResponse, String> {
+    // WIT -> reqwest request
+    let method = match request.method.as_str() {
+        "GET" => reqwest::Method::GET,
+        "POST" => reqwest::Method::POST,
+        "PUT" => reqwest::Method::PUT,
+        "DELETE" => reqwest::Method::DELETE,
+        _ => return Err(format!("unsupported HTTP method: {}", request.method)),
+    };
+
+    let mut req_builder = client.request(method, &request.url);
+    if let Some(timeout) = network.next_timeout(run_started)? {
+        req_builder = req_builder.timeout(timeout);
+    }
+
+    // add headers
+    for (key, value) in request.headers {
+        req_builder = req_builder.header(&key, &value);
+    }
+
+    // add body if present
+    if let Some(body) = request.body {
+        req_builder = req_builder.body(body);
+    }
+
+    // execute request
+    match req_builder.send().await {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            let headers = response
+                .headers()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+                .collect();
+
+            let body = read_body(response, network.max_response_bytes()).await?;
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        }
+        Err(e) => Err(format!("HTTP request failed: {e}")),
+    }
+}
+
+// read the response body, failing as soon as it exceeds the size cap
+async fn read_body(
+    mut response: reqwest::Response,
+    max_bytes: Option<usize>,
+) -> Result<Vec<u8>, String> {
+    let limit = max_bytes.unwrap_or(usize::MAX);
+    if response
+        .content_length()
+        .is_some_and(|len| len > limit as u64)
+    {
+        return Err(format!("response body exceeds {limit} bytes"));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("failed to read response body: {e}"))?
+    {
+        if body.len() + chunk.len() > limit {
+            return Err(format!("response body exceeds {limit} bytes"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
@@ -0,0 +1,312 @@
+// inspect
+// checks a wasm binary against the newton-provider world without running it
+
+use crate::adapt;
+use crate::cache::ComponentCache;
+use crate::sim;
+use crate::worlds::{self, WorldVersion, PROVIDER_PACKAGE};
+use serde::Serialize;
+use wasmparser::{Parser, Payload};
+use wasmtime::component::types::{ComponentFunc, ComponentItem, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmKind {
+    Component,
+    CoreModule,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Compatible,
+    CompatibleWithWarnings,
+    Incompatible,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub kind: WasmKind,
+    /// the newton:provider world version the component targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub world: Option<String>,
+    pub imports: Vec<String>,
+    pub exports: Vec<String>,
+    pub findings: Vec<Finding>,
+    pub verdict: Verdict,
+}
+
+impl Report {
+    fn new(kind: WasmKind) -> Self {
+        Self {
+            kind,
+            world: None,
+            imports: Vec::new(),
+            exports: Vec::new(),
+            findings: Vec::new(),
+            verdict: Verdict::Compatible,
+        }
+    }
+
+    fn add(&mut self, severity: Severity, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            message: message.into(),
+        });
+    }
+
+    fn finish(mut self) -> Self {
+        self.verdict = match self.findings.iter().map(|f| f.severity).max() {
+            Some(Severity::Error) => Verdict::Incompatible,
+            Some(Severity::Warning) => Verdict::CompatibleWithWarnings,
+            _ => Verdict::Compatible,
+        };
+        self
+    }
+
+    pub fn print_human(&self) {
+        println!(
+            "kind: {}",
+            match self.kind {
+                WasmKind::Component => "component",
+                WasmKind::CoreModule => "core module",
+                WasmKind::Unknown => "not a wasm binary",
+            }
+        );
+        if let Some(world) = &self.world {
+            println!("world: {world}");
+        }
+        println!("imports:");
+        for import in &self.imports {
+            println!("  {import}");
+        }
+        println!("exports:");
+        for export in &self.exports {
+            println!("  {export}");
+        }
+        if !self.findings.is_empty() {
+            println!("findings:");
+            for finding in &self.findings {
+                let label = match finding.severity {
+                    Severity::Info => "info",
+                    Severity::Warning => "warning",
+                    Severity::Error => "error",
+                };
+                println!("  {label}: {}", finding.message);
+            }
+        }
+        let world = self
+            .world
+            .clone()
+            .unwrap_or_else(|| WorldVersion::current().name());
+        println!(
+            "verdict: {}",
+            match self.verdict {
+                Verdict::Compatible => format!("compatible with {world}"),
+                Verdict::CompatibleWithWarnings =>
+                    format!("compatible with {world}, with warnings"),
+                Verdict::Incompatible => format!("NOT compatible with {world}"),
+            }
+        );
+    }
+}
+
+pub fn inspect(wasm_bytes: &[u8], cache: Option<&ComponentCache>) -> Report {
+    let report = if Parser::is_component(wasm_bytes) {
+        inspect_component(wasm_bytes, cache)
+    } else if Parser::is_core_wasm(wasm_bytes) {
+        inspect_core_module(wasm_bytes, cache)
+    } else {
+        let mut report = Report::new(WasmKind::Unknown);
+        report.add(
+            Severity::Error,
+            "file is not a WebAssembly module or component",
+        );
+        report
+    };
+    report.finish()
+}
+
+fn inspect_component(wasm_bytes: &[u8], cache: Option<&ComponentCache>) -> Report {
+    let mut report = Report::new(WasmKind::Component);
+
+    let compiled = sim::engine().and_then(|engine| {
+        let (component, _) = sim::compile(&engine, wasm_bytes, cache)?;
+        Ok((engine, component))
+    });
+    let (engine, component) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            report.add(
+                Severity::Error,
+                format!("component failed to compile: {e:#}"),
+            );
+            return report;
+        }
+    };
+
+    let ty = component.component_type();
+    report.imports = ty
+        .imports(&engine)
+        .map(|(name, _)| name.to_string())
+        .collect();
+    let world = match worlds::select(report.imports.iter().map(String::as_str)) {
+        Ok(world) => {
+            report.world = Some(world.name());
+            if let Some(warning) = world.support_warning() {
+                report.add(Severity::Warning, warning);
+            }
+            Some(world)
+        }
+        Err(e) => {
+            report.add(Severity::Error, format!("{e:#}"));
+            None
+        }
+    };
+    for name in report.imports.clone() {
+        if let Some((severity, message)) = classify_import(&name, world) {
+            report.add(severity, message);
+        }
+    }
+
+    let mut has_run = false;
+    for (name, item) in ty.exports(&engine) {
+        report.exports.push(name.to_string());
+        if name == "run" {
+            has_run = true;
+            match &item {
+                ComponentItem::ComponentFunc(func) if is_run_signature(func) => {}
+                _ => report.add(
+                    Severity::Error,
+                    "export `run` must be `func(input: string) -> result<string, string>`",
+                ),
+            }
+        }
+    }
+    if !has_run {
+        report.add(Severity::Error, "component does not export `run`");
+    }
+
+    // the definitive check: link against exactly what the operator provides
+    if world.is_some() {
+        let linked =
+            sim::linker(&engine).and_then(|linker| worlds::bind(&engine, &component, &linker));
+        if let Err(e) = linked {
+            report.add(
+                Severity::Error,
+                format!("component does not link against the host: {e:#}"),
+            );
+        }
+    }
+
+    report
+}
+
+// `world` is the version the component targets; version mismatches are
+// reported when it is selected
+fn classify_import(name: &str, world: Option<&WorldVersion>) -> Option<(Severity, String)> {
+    if let Some((interface, _)) = worlds::provider_import(name) {
+        return match world {
+            Some(world) if !world.interfaces.contains(&interface) => Some((
+                Severity::Error,
+                format!(
+                    "imports unknown interface {name}, which {} does not define",
+                    world.name()
+                ),
+            )),
+            _ => None,
+        };
+    }
+    let (package, _) = name.split_once('/')?;
+
+    match package {
+        "wasi:cli" | "wasi:io" | "wasi:clocks" | "wasi:random" => None,
+        "wasi:filesystem" => Some((
+            Severity::Warning,
+            format!("imports {name}; operators do not preopen any directories"),
+        )),
+        "wasi:sockets" => Some((
+            Severity::Warning,
+            format!("imports {name}; raw sockets may be denied by operator sandboxing, use {PROVIDER_PACKAGE}/http instead"),
+        )),
+        "wasi:http" => Some((
+            Severity::Warning,
+            format!("imports {name}; prefer {PROVIDER_PACKAGE}/http for outbound requests"),
+        )),
+        _ => Some((Severity::Error, format!("imports {name}, which the operator does not provide"))),
+    }
+}
+
+fn is_run_signature(func: &ComponentFunc) -> bool {
+    let params: Vec<_> = func.params().collect();
+    let results: Vec<_> = func.results().collect();
+    matches!(params.as_slice(), [(_, Type::String)])
+        && matches!(
+            results.as_slice(),
+            [Type::Result(result)]
+                if matches!(result.ok(), Some(Type::String)) && matches!(result.err(), Some(Type::String))
+        )
+}
+
+fn inspect_core_module(wasm_bytes: &[u8], cache: Option<&ComponentCache>) -> Report {
+    match adapt::componentize(wasm_bytes) {
+        Ok(component) => {
+            let mut report = inspect_component(&component, cache);
+            report.kind = WasmKind::CoreModule;
+            report.findings.insert(0, Finding {
+                severity: Severity::Warning,
+                message: "wasip1 core module; op-sim adapts it to a component with the WASI preview1 adapter, \
+                          but operators run components, so deploy a wasm32-wasip2 build"
+                    .to_string(),
+            });
+            report
+        }
+        Err(e) => {
+            let mut report = Report::new(WasmKind::CoreModule);
+            list_core_items(wasm_bytes, &mut report);
+            report.add(Severity::Error, format!("{e:#}"));
+            report
+        }
+    }
+}
+
+// lists a core module's imports as `module::name` and its exports
+fn list_core_items(wasm_bytes: &[u8], report: &mut Report) {
+    for payload in Parser::new(0).parse_all(wasm_bytes) {
+        match payload {
+            Ok(Payload::ImportSection(reader)) => {
+                report.imports.extend(
+                    reader
+                        .into_iter()
+                        .flatten()
+                        .map(|import| format!("{}::{}", import.module, import.name)),
+                );
+            }
+            Ok(Payload::ExportSection(reader)) => {
+                report.exports.extend(
+                    reader
+                        .into_iter()
+                        .flatten()
+                        .map(|export| export.name.to_string()),
+                );
+            }
+            Ok(_) => {}
+            Err(_) => return,
+        }
+    }
+}
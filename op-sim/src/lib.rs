@@ -0,0 +1,41 @@
+// op-sim
+// simulates operator execution of a wasm: the library behind the op-sim CLI
+//
+// usage:
+//
+//     let simulator = op_sim::Simulator::new(op_sim::SimulatorConfig::default())?;
+//     let result = simulator.simulate(&wasm_bytes, input).await?;
+//     match result.outcome? {
+//         Ok(output) => println!("{output}"),
+//         Err(error) => eprintln!("WASM execution error: {error}"),
+//     }
+
+pub mod adapt;
+pub mod batch;
+pub mod cache;
+pub mod cassette;
+pub mod consensus;
+pub mod determinism;
+pub mod envelope;
+pub mod faults;
+pub mod http;
+pub mod inspect;
+pub mod limits;
+pub mod log;
+pub mod metrics;
+pub mod mock;
+pub mod network;
+pub mod profile;
+pub mod secrets;
+pub mod serve;
+pub mod sim;
+pub mod trace;
+pub mod variance;
+pub mod worlds;
+
+pub use http::{HttpHost, HttpSource};
+pub use newton::provider::http::{HttpRequest, HttpResponse};
+pub use sim::{Simulation, SimulationResult, Simulator, SimulatorConfig};
+
+// the host implements the newest world's interfaces with its types
+pub use worlds::v0_2_0::newton;
@@ -0,0 +1,233 @@
+// limits
+// wall-clock deadline and memory, table and instance caps for a guest run
+
+use crate::metrics::MemoryTracker;
+use std::time::Duration;
+use wasmtime::{Engine, Trap};
+
+// how often the engine's epoch advances while a deadline is set
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+// epoch deadline used when no wall-clock deadline is set, far enough away to never be reached
+const NO_EPOCH_DEADLINE: u64 = u64::MAX / 2;
+
+// wasmtime's own defaults for instance, table and memory counts
+const DEFAULT_MAX_COUNT: usize = 10_000;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct LimitArgs {
+    /// Abort the run after this many milliseconds, including time spent waiting on host fetches
+    #[arg(long, value_name = "MS")]
+    pub deadline_ms: Option<u64>,
+    /// Abort the run when the guest's linear memories grow past this many bytes in total
+    #[arg(long, value_name = "BYTES")]
+    pub max_memory_bytes: Option<usize>,
+    /// Abort the run when any table grows past this many elements
+    #[arg(long, value_name = "N")]
+    pub max_table_elements: Option<usize>,
+    /// Maximum number of core instances the guest may create
+    #[arg(long, value_name = "N")]
+    pub max_instances: Option<usize>,
+    /// Maximum number of tables the guest may create
+    #[arg(long, value_name = "N")]
+    pub max_tables: Option<usize>,
+    /// Maximum number of linear memories the guest may create
+    #[arg(long, value_name = "N")]
+    pub max_memories: Option<usize>,
+}
+
+/// Limits applied to every run of a simulator, on top of the fuel budget.
+/// `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub deadline: Option<Duration>,
+    pub max_memory_bytes: Option<usize>,
+    pub max_table_elements: Option<usize>,
+    pub max_instances: Option<usize>,
+    pub max_tables: Option<usize>,
+    pub max_memories: Option<usize>,
+}
+
+impl Limits {
+    pub fn from_args(args: &LimitArgs) -> Self {
+        Self {
+            deadline: args.deadline_ms.map(Duration::from_millis),
+            max_memory_bytes: args.max_memory_bytes,
+            max_table_elements: args.max_table_elements,
+            max_instances: args.max_instances,
+            max_tables: args.max_tables,
+            max_memories: args.max_memories,
+        }
+    }
+
+    /// Epoch ticks until the deadline, for `Store::set_epoch_deadline`.
+    pub fn epoch_deadline(&self) -> u64 {
+        match self.deadline {
+            Some(deadline) => (deadline.as_nanos() / EPOCH_TICK.as_nanos()).max(1) as u64,
+            None => NO_EPOCH_DEADLINE,
+        }
+    }
+}
+
+/// Advances the engine's epoch every `tick` until the engine is dropped.
+pub fn start_epoch_ticker(engine: &Engine, tick: Duration) {
+    let engine = engine.weak();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(tick);
+        match engine.upgrade() {
+            Some(engine) => engine.increment_epoch(),
+            None => break,
+        }
+    });
+}
+
+/// Why a run was stopped by the host rather than finishing or failing on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Fuel {
+        budget: u64,
+    },
+    Deadline {
+        deadline: Duration,
+    },
+    Memory {
+        limit: usize,
+    },
+    TableElements {
+        limit: usize,
+    },
+    /// too many instances, tables or memories
+    Count(String),
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::Fuel { budget } => write!(f, "fuel budget of {budget} exhausted"),
+            LimitExceeded::Deadline { deadline } => {
+                write!(
+                    f,
+                    "wall-clock deadline of {} ms exceeded",
+                    deadline.as_millis()
+                )
+            }
+            LimitExceeded::Memory { limit } => write!(f, "memory limit of {limit} bytes exceeded"),
+            LimitExceeded::TableElements { limit } => {
+                write!(f, "table limit of {limit} elements exceeded")
+            }
+            LimitExceeded::Count(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+    /// Process exit code for a run stopped by this limit. Guest errors and
+    /// other failures exit with 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LimitExceeded::Fuel { .. } => 3,
+            LimitExceeded::Deadline { .. } => 4,
+            LimitExceeded::Memory { .. }
+            | LimitExceeded::TableElements { .. }
+            | LimitExceeded::Count(_) => 5,
+        }
+    }
+
+    pub fn find(error: &anyhow::Error) -> Option<&LimitExceeded> {
+        // `downcast_ref` sees context added with `anyhow::Context`, `chain`
+        // sees causes wrapped by other error types
+        error.downcast_ref::<LimitExceeded>().or_else(|| {
+            error
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<LimitExceeded>())
+        })
+    }
+
+    /// Recognizes the traps and errors wasmtime raises when a limit is hit
+    /// and tags the error with the limit, so callers can tell them apart.
+    pub fn classify(error: anyhow::Error, fuel_budget: u64, limits: &Limits) -> anyhow::Error {
+        if Self::find(&error).is_some() {
+            return error;
+        }
+        let limit = match error.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => LimitExceeded::Fuel {
+                budget: fuel_budget,
+            },
+            Some(Trap::Interrupt) => match limits.deadline {
+                Some(deadline) => LimitExceeded::Deadline { deadline },
+                None => return error,
+            },
+            _ => match error
+                .chain()
+                .map(|cause| cause.to_string())
+                .find(|message| message.starts_with("resource limit exceeded"))
+            {
+                Some(message) => LimitExceeded::Count(message),
+                None => return error,
+            },
+        };
+        error.context(limit)
+    }
+}
+
+/// Resource limiter for a store: enforces [`Limits`] and records memory
+/// growth for the metrics.
+pub struct Limiter {
+    limits: Limits,
+    pub memory: MemoryTracker,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            memory: MemoryTracker::default(),
+        }
+    }
+}
+
+impl wasmtime::ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        let growth = desired.saturating_sub(current);
+        if let Some(limit) = self.limits.max_memory_bytes {
+            if self.memory.peak_memory_bytes() + growth > limit {
+                return Err(LimitExceeded::Memory { limit }.into());
+            }
+        }
+        self.memory.record_growth(growth);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        if let Some(limit) = self.limits.max_table_elements {
+            if desired > limit {
+                return Err(LimitExceeded::TableElements { limit }.into());
+            }
+        }
+        Ok(true)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.max_instances.unwrap_or(DEFAULT_MAX_COUNT)
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.max_tables.unwrap_or(DEFAULT_MAX_COUNT)
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.max_memories.unwrap_or(DEFAULT_MAX_COUNT)
+    }
+}
@@ -0,0 +1,92 @@
+// log
+// the newton:provider/log host: collects guest log entries, and host notes, for the run report
+
+use crate::metrics::millis;
+use crate::newton::provider::log::Level;
+use serde::Serialize;
+use std::time::Instant;
+
+// upper bound on collected entries; later entries are counted but dropped
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// milliseconds from the start of the run
+    pub at_ms: f64,
+    pub level: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<(String, String)>,
+}
+
+pub struct LogCollector {
+    run_started: Instant,
+    // print each entry to stderr as it is logged
+    stream: bool,
+    pub entries: Vec<LogEntry>,
+    // entries logged past MAX_ENTRIES
+    pub dropped: usize,
+}
+
+impl LogCollector {
+    pub fn new(stream: bool) -> Self {
+        Self {
+            run_started: Instant::now(),
+            stream,
+            entries: Vec::new(),
+            dropped: 0,
+        }
+    }
+}
+
+impl LogCollector {
+    /// Records an entry from the host rather than the guest, such as a
+    /// wasi:http request the network policy denied.
+    pub fn host(&mut self, level: &'static str, message: String) {
+        let entry = LogEntry {
+            at_ms: millis(self.run_started.elapsed()),
+            level,
+            message,
+            fields: vec![("source".to_string(), "op-sim".to_string())],
+        };
+        self.push("op-sim", entry);
+    }
+
+    fn push(&mut self, origin: &str, entry: LogEntry) {
+        if self.stream {
+            let fields: String = entry
+                .fields
+                .iter()
+                .map(|(key, value)| format!(" {key}={value}"))
+                .collect();
+            eprintln!("[{origin} {}] {}{fields}", entry.level, entry.message);
+        }
+        if self.entries.len() < MAX_ENTRIES {
+            self.entries.push(entry);
+        } else {
+            self.dropped += 1;
+        }
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Trace => "trace",
+        Level::Debug => "debug",
+        Level::Info => "info",
+        Level::Warn => "warn",
+        Level::Error => "error",
+    }
+}
+
+impl crate::newton::provider::log::Host for LogCollector {
+    async fn log(&mut self, level: Level, message: String, fields: Vec<(String, String)>) {
+        let entry = LogEntry {
+            at_ms: millis(self.run_started.elapsed()),
+            level: level_name(level),
+            message,
+            fields,
+        };
+        self.push("guest", entry);
+    }
+}
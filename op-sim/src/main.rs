@@ -1,176 +1,557 @@
 // op-sim
-// simulates operator execution of a wasm
+// command line front end to the op-sim library: simulates operator execution of a wasm
 //
-// usage: op-sim <wasm_file> <input_json>
+// usage: op-sim <wasm_file> <input_json> [--record <cassette> | --replay <cassette> | --mock <rules>]
+//        op-sim <wasm_file> <input_json> [--faults <rules>] [--fault-seed <seed>]
+//        op-sim <wasm_file> <input_json> [--http-trace <trace.jsonl>] [--profile <profile.json | stacks.folded>]
+//        op-sim <wasm_file> <input_json> [--deadline-ms <ms>] [--max-memory-bytes <bytes>]
+//        op-sim <wasm_file> <input_json> [--frozen-time <time>] [--random-seed <seed>] [--repeat <n>]
+//        op-sim batch <wasm_file> <inputs.jsonl> [--concurrency <n>] [--results <results.jsonl>]
+//        op-sim consensus <wasm_file> <input_json> [--operators <k>] [--quorum-threshold-percentage <pct>]
+//        op-sim inspect <wasm_file> [--json]
+//        op-sim serve [--listen <addr>]
 
-use clap::Parser;
-use std::path::PathBuf;
-use wasmtime::component::{bindgen, Component, HasSelf, Linker, ResourceTable};
-use wasmtime::{Config, Engine};
-use wasmtime_wasi::p2::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
-use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
-
-bindgen!({
-    path: "wit",
-    async: true,
-});
-
-use newton::provider::http::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use op_sim::batch;
+use op_sim::cache::CacheArgs;
+use op_sim::cassette::Cassette;
+use op_sim::consensus::{self, Comparison};
+use op_sim::determinism::{self, Determinism, DeterminismArgs};
+use op_sim::envelope::Envelope;
+use op_sim::faults::{self, FaultRules};
+use op_sim::http::{HttpMode, HttpSource};
+use op_sim::inspect;
+use op_sim::limits::{LimitArgs, LimitExceeded, Limits};
+use op_sim::metrics::millis;
+use op_sim::mock::MockRules;
+use op_sim::network::{NetworkArgs, NetworkPolicy};
+use op_sim::secrets::SecretsProvider;
+use op_sim::serve::{self, Server};
+use op_sim::sim::GuestOutput;
+use op_sim::trace;
+use op_sim::variance::{self, NumericVariance};
+use op_sim::{Simulation, Simulator, SimulatorConfig};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Parser)]
 #[command(name = "op-sim")]
 #[command(about = "Simulate operator WASM execution")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a component once per input of a JSONL file
+    Batch(Box<BatchArgs>),
+    /// List a component's imports and exports and check them against the newton-provider world
+    Inspect(InspectArgs),
+    /// Run a component as several simulated operators and check whether their outputs reach consensus
+    Consensus(Box<ConsensusArgs>),
+    /// Serve simulate and inspect calls as JSON-RPC over HTTP, keeping the engine and compiled components warm
+    Serve(Box<ServeArgs>),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Path to the WASM component file
+    #[arg(required = true)]
+    wasm_file: Option<PathBuf>,
+    /// Input JSON string
+    #[arg(required = true)]
+    input_json: Option<String>,
+    /// Record every HTTP fetch to this cassette file
+    #[arg(long, value_name = "CASSETTE", conflicts_with_all = ["replay", "mock"])]
+    record: Option<PathBuf>,
+    /// Write every HTTP fetch, with credentials and secret values redacted, as JSONL to this file
+    #[arg(long, value_name = "FILE")]
+    http_trace: Option<PathBuf>,
+    /// Sample guest stacks and write them to this file: folded stacks for flamegraphs if it ends in .folded or .txt, a Firefox profiler file otherwise
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+    #[command(flatten)]
+    host: HostArgs,
+    #[command(flatten)]
+    cache: CacheArgs,
+    /// Print fuel, memory, fetch and timing metrics to stderr
+    #[arg(long)]
+    metrics: bool,
+    /// Write fuel, memory, fetch and timing metrics as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    metrics_json: Option<PathBuf>,
+    /// Output format: the raw guest output, or a JSON envelope with status, output, guest stdio and timings
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Run the guest N times and report any run whose outcome differs from the first
+    #[arg(long, value_name = "N", default_value_t = 1, conflicts_with = "record")]
+    repeat: usize,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// Path to the WASM component file
+    wasm_file: PathBuf,
+    /// JSONL file with one input per line
+    inputs: PathBuf,
+    /// Maximum number of inputs run at the same time
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    concurrency: usize,
+    /// Write JSONL results to this file instead of stdout
+    #[arg(long, value_name = "FILE")]
+    results: Option<PathBuf>,
+    #[command(flatten)]
+    host: HostArgs,
+    #[command(flatten)]
+    cache: CacheArgs,
+}
+
+#[derive(Args)]
+struct ConsensusArgs {
     /// Path to the WASM component file
     wasm_file: PathBuf,
     /// Input JSON string
     input_json: String,
+    /// Number of simulated operators [default: the number of operator fixtures, or 3]
+    #[arg(long, value_name = "K")]
+    operators: Option<usize>,
+    /// Mock rules file for one operator, in operator order (repeatable)
+    #[arg(long, value_name = "RULES", conflicts_with = "operator_replay")]
+    operator_mock: Vec<PathBuf>,
+    /// Cassette file for one operator, in operator order (repeatable)
+    #[arg(long, value_name = "CASSETTE")]
+    operator_replay: Vec<PathBuf>,
+    /// Scale numbers with a fractional part in fetched JSON by up to this many percent, differently per operator
+    #[arg(long, value_name = "PERCENT", value_parser = variance::parse_percent)]
+    numeric_variance: Option<f64>,
+    /// Seed for --numeric-variance; operator i uses SEED + i
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    variance_seed: u64,
+    /// Freeze each operator's clock, with operator i running this many milliseconds after operator 0
+    #[arg(long, value_name = "MS")]
+    clock_skew_ms: Option<u64>,
+    /// Percentage of operators that must agree, as in the task's quorumThresholdPercentage
+    #[arg(
+        long,
+        alias = "threshold",
+        value_name = "PERCENT",
+        default_value_t = 100.0,
+        value_parser = consensus::parse_threshold
+    )]
+    quorum_threshold_percentage: f64,
+    /// How outputs are compared when deciding consensus
+    #[arg(long, value_enum, default_value_t = Comparison::Canonical)]
+    compare: Comparison,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    host: HostArgs,
+    #[command(flatten)]
+    cache: CacheArgs,
 }
 
-pub struct HttpProvider;
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8787")]
+    listen: SocketAddr,
+    #[command(flatten)]
+    host: HostArgs,
+    #[command(flatten)]
+    cache: CacheArgs,
+}
 
-// custom provider
-impl newton::provider::http::Host for HttpProvider {
-    async fn fetch(&mut self, request: HttpRequest) -> Result<HttpResponse, String> {
-        // WIT -> reqwest request
-        let method = match request.method.as_str() {
-            "GET" => reqwest::Method::GET,
-            "POST" => reqwest::Method::POST,
-            "PUT" => reqwest::Method::PUT,
-            "DELETE" => reqwest::Method::DELETE,
-            _ => return Err(format!("unsupported HTTP method: {}", request.method)),
-        };
+#[derive(Args)]
+struct InspectArgs {
+    /// Path to the WASM file
+    wasm_file: PathBuf,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    cache: CacheArgs,
+}
 
-        let client = reqwest::Client::new();
-        let mut req_builder = client.request(method, &request.url);
+/// Host options shared by single and batch runs.
+#[derive(Args)]
+struct HostArgs {
+    /// Serve HTTP fetches from this cassette file instead of the network
+    #[arg(long, value_name = "CASSETTE", conflicts_with = "mock")]
+    replay: Option<PathBuf>,
+    /// Serve HTTP fetches from a TOML or JSON file of mock rules instead of the network
+    #[arg(long, value_name = "RULES")]
+    mock: Option<PathBuf>,
+    /// TOML or JSON file of secret id to value, served to the guest's secrets interface
+    #[arg(long, value_name = "FILE")]
+    secrets_file: Option<PathBuf>,
+    /// Look up secrets missing from the file in environment variables named <PREFIX><SECRET_ID>
+    #[arg(long, value_name = "PREFIX")]
+    secrets_env_prefix: Option<String>,
+    /// Fail secret lookups that cannot be resolved instead of returning an empty string
+    #[arg(long)]
+    strict_secrets: bool,
+    /// TOML or JSON file of faults (latency, errors, bad statuses or bodies) to inject into HTTP fetches
+    #[arg(long, value_name = "FILE")]
+    faults: Option<PathBuf>,
+    /// Seed for faults with a probability, instead of the seed in the faults file
+    #[arg(long, value_name = "SEED", requires = "faults")]
+    fault_seed: Option<u64>,
+    /// Print entries the guest logs through newton:provider/log to stderr as they are logged
+    #[arg(long)]
+    stream_logs: bool,
+    #[command(flatten)]
+    network: NetworkArgs,
+    #[command(flatten)]
+    limits: LimitArgs,
+    #[command(flatten)]
+    determinism: DeterminismArgs,
+}
 
-        // add headers
-        for (key, value) in request.headers {
-            req_builder = req_builder.header(&key, &value);
-        }
+impl HostArgs {
+    fn config(&self, record: bool, cache: &CacheArgs) -> anyhow::Result<SimulatorConfig> {
+        let http = if let Some(path) = &self.replay {
+            HttpSource::Replay(Cassette::load(path)?)
+        } else if let Some(path) = &self.mock {
+            HttpSource::Mock(Arc::new(MockRules::load(path)?))
+        } else if record {
+            HttpSource::Record
+        } else {
+            HttpSource::Live
+        };
 
-        // add body if present
-        if let Some(body) = request.body {
-            req_builder = req_builder.body(body);
+        let mut secrets =
+            SecretsProvider::new(self.secrets_env_prefix.clone(), self.strict_secrets);
+        if let Some(path) = &self.secrets_file {
+            secrets.load_file(path)?;
         }
 
-        // execute request
-        match req_builder.send().await {
-            Ok(response) => {
-                let status = response.status().as_u16();
-                let headers = response
-                    .headers()
-                    .iter()
-                    .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
-                    .collect();
-
-                match response.bytes().await {
-                    Ok(body) => Ok(HttpResponse {
-                        status,
-                        headers,
-                        body: body.to_vec(),
-                    }),
-                    Err(e) => Err(format!("failed to read response body: {e}")),
-                }
+        let faults = match &self.faults {
+            Some(path) => {
+                let rules = FaultRules::load(path)?;
+                Some(Arc::new(match self.fault_seed {
+                    Some(seed) => rules.with_seed(seed),
+                    None => rules,
+                }))
             }
-            Err(e) => Err(format!("HTTP request failed: {e}")),
-        }
+            None => None,
+        };
+
+        Ok(SimulatorConfig {
+            http,
+            secrets,
+            network: Arc::new(NetworkPolicy::from_args(&self.network)?),
+            cache: cache.cache(),
+            limits: Limits::from_args(&self.limits),
+            determinism: Determinism::from_args(&self.determinism),
+            variance: None,
+            faults,
+            http_trace: false,
+            stream_logs: self.stream_logs,
+            profile: false,
+        })
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
-    let input = &cli.input_json;
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
 
+fn load_simulation(wasm_file: &Path, config: SimulatorConfig) -> anyhow::Result<Simulation> {
     // Read WASM file
-    let wasm_bytes = std::fs::read(&cli.wasm_file)?;
+    let wasm_bytes = std::fs::read(wasm_file)
+        .with_context(|| format!("failed to read {}", wasm_file.display()))?;
+    let simulation = Simulator::new(config)?.load(&wasm_bytes)?;
+    if let Some(warning) = simulation.world().support_warning() {
+        eprintln!("Warning: {warning}");
+    }
+    Ok(simulation)
+}
 
-    let mut wasm_config = Config::new();
-    wasm_config.wasm_component_model(true);
-    wasm_config.async_support(true);
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Batch(args)) => batch(*args).await,
+        Some(Command::Inspect(args)) => inspect(args),
+        Some(Command::Consensus(args)) => consensus(*args).await,
+        Some(Command::Serve(args)) => {
+            let server = Server::new(args.host.config(false, &args.cache)?)?;
+            serve::serve(Arc::new(server), args.listen).await
+        }
+        None => run(cli.run).await,
+    }
+}
 
-    wasm_config.consume_fuel(true);
-    wasm_config.max_wasm_stack(1024 * 1024 * 32); // 32 MiB
-    wasm_config.async_stack_size(1024 * 1024 * 64); // 64 MiB
+async fn run(args: RunArgs) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let wasm_file = args.wasm_file.as_deref().expect("required by clap");
+    let input = args.input_json.as_deref().expect("required by clap");
 
-    let engine = Engine::new(&wasm_config)?;
-    let component = Component::new(&engine, wasm_bytes)?;
+    // in json mode guest stdio is captured into the envelope; otherwise guest
+    // stdout goes to stderr so that stdout only carries the result
+    let guest_output = match args.output {
+        OutputFormat::Json => GuestOutput::capture(),
+        OutputFormat::Text => GuestOutput::Stderr,
+    };
 
-    struct MyCtx {
-        table: ResourceTable,
-        wasi: WasiCtx,
-        http: HttpProvider,
-        wasi_http_ctx: WasiHttpCtx,
-    }
+    let mut consistent = true;
+    let run = match args
+        .host
+        .config(args.record.is_some(), &args.cache)
+        .and_then(|config| {
+            let config = SimulatorConfig {
+                http_trace: args.http_trace.is_some(),
+                profile: args.profile.is_some(),
+                ..config
+            };
+            load_simulation(wasm_file, config)
+        }) {
+        Ok(simulation) => {
+            let run = simulation.run(input, &guest_output).await;
+            if let Ok(first) = &run {
+                consistent = check_repeats(&simulation, input, &first.outcome, args.repeat).await;
+            }
+            run
+        }
+        Err(e) => Err(e),
+    };
 
-    impl IoView for MyCtx {
-        fn table(&mut self) -> &mut ResourceTable {
-            &mut self.table
+    let (outcome, mut timings, applied_faults, logs) = match run {
+        Ok(mut run) => {
+            run.metrics.wall_time_ms = millis(started.elapsed());
+            if args.metrics {
+                run.metrics.print_human();
+            }
+            if let Some(path) = &args.metrics_json {
+                std::fs::write(path, serde_json::to_string_pretty(&run.metrics)?)?;
+            }
+            if let (Some(path), HttpMode::Record(cassette)) = (&args.record, &run.http.mode) {
+                cassette.save(path)?;
+                eprintln!(
+                    "Recorded {} HTTP interaction(s) to {}",
+                    cassette.interactions.len(),
+                    path.display()
+                );
+            }
+            if let (Some(path), Some(entries)) = (&args.http_trace, &run.http_trace) {
+                trace::save(path, entries)?;
+                eprintln!(
+                    "Traced {} HTTP fetch(es) to {}",
+                    entries.len(),
+                    path.display()
+                );
+            }
+            if let (Some(path), Some(profile)) = (&args.profile, &run.profile) {
+                profile.save(path)?;
+                eprintln!(
+                    "Wrote a profile of {} sample(s) to {}",
+                    profile.samples,
+                    path.display()
+                );
+            }
+            if !run.secrets.is_strict() && !run.secrets.missing().is_empty() {
+                eprintln!("Warning: the following secret(s) could not be resolved:");
+                for secret_id in run.secrets.missing() {
+                    eprintln!("  {secret_id}");
+                }
+            }
+            let applied_faults = run.http.applied_faults().to_vec();
+            if args.output == OutputFormat::Text && !applied_faults.is_empty() {
+                faults::print_summary(&applied_faults);
+            }
+            (
+                run.outcome,
+                run.timings,
+                applied_faults,
+                (run.logs, run.logs_dropped),
+            )
         }
-    }
+        Err(e) => (Err(e), Default::default(), Vec::new(), Default::default()),
+    };
+    timings.wall_time_ms = millis(started.elapsed());
 
-    impl WasiView for MyCtx {
-        fn ctx(&mut self) -> &mut WasiCtx {
-            &mut self.wasi
+    match args.output {
+        OutputFormat::Json => {
+            let (stdout, stderr) = guest_output.contents();
+            let mut envelope = Envelope::new(&outcome, &stdout, &stderr, timings);
+            envelope.faults = applied_faults;
+            (envelope.logs, envelope.logs_dropped) = logs;
+            println!("{}", serde_json::to_string_pretty(&envelope)?);
+            match &outcome {
+                Err(e) => std::process::exit(exit_code(e)),
+                Ok(Err(_)) => std::process::exit(1),
+                Ok(Ok(_)) => {}
+            }
         }
+        OutputFormat::Text => match outcome.inspect_err(|e| {
+            if let Some(limit) = LimitExceeded::find(e) {
+                eprintln!("WASM execution aborted: {limit}");
+                std::process::exit(limit.exit_code());
+            }
+        })? {
+            Ok(output) => println!("{}", output),
+            Err(error) => {
+                eprintln!("WASM execution error: {}", error);
+                std::process::exit(1);
+            }
+        },
     }
 
-    impl WasiHttpView for MyCtx {
-        fn ctx(&mut self) -> &mut WasiHttpCtx {
-            &mut self.wasi_http_ctx
-        }
+    if !consistent {
+        std::process::exit(NONDETERMINISTIC_EXIT);
     }
+    Ok(())
+}
 
-    // wasi context (exact same as operator)
-    let ctx = MyCtx {
-        table: ResourceTable::new(),
-        wasi_http_ctx: WasiHttpCtx::new(),
-        http: HttpProvider,
-        wasi: WasiCtxBuilder::new()
-            .args(&["plugin", input]) // pass input as command line argument
-            .inherit_stdin()
-            .inherit_stdout()
-            .inherit_stderr()
-            .inherit_network() // allow network access (eg for HTTP requests)
-            .build(),
-    };
+/// Exit code when `--repeat` runs disagree.
+const NONDETERMINISTIC_EXIT: i32 = 6;
 
-    let mut store = wasmtime::Store::new(&engine, ctx);
+/// Runs the guest `repeat - 1` more times and reports every run whose outcome
+/// differs from the first. Returns whether all runs agreed.
+async fn check_repeats(
+    simulation: &Simulation,
+    input: &str,
+    first: &anyhow::Result<Result<String, String>>,
+    repeat: usize,
+) -> bool {
+    let mut consistent = true;
+    for index in 2..=repeat {
+        let outcome = match simulation.run(input, &GuestOutput::capture()).await {
+            Ok(run) => run.outcome,
+            Err(e) => Err(e),
+        };
+        let differences = determinism::outcome_differences(first, &outcome);
+        if !differences.is_empty() {
+            consistent = false;
+            eprintln!("run {index} of {repeat} differs from run 1:");
+            for difference in differences {
+                eprintln!("  {difference}");
+            }
+        }
+    }
+    if repeat > 1 && consistent {
+        eprintln!("all {repeat} runs produced the same outcome");
+    }
+    consistent
+}
 
-    // Set fuel
-    store.set_fuel(100_000_000)?;
+/// Exit code for a run op-sim could not finish: the limit's own code, or 1.
+fn exit_code(error: &anyhow::Error) -> i32 {
+    LimitExceeded::find(error).map_or(1, LimitExceeded::exit_code)
+}
 
-    let mut linker = Linker::new(&engine);
-    linker.allow_shadowing(true);
+async fn batch(args: BatchArgs) -> anyhow::Result<()> {
+    let inputs = batch::read_inputs(&args.inputs)?;
+    let simulation = Arc::new(load_simulation(
+        &args.wasm_file,
+        args.host.config(false, &args.cache)?,
+    )?);
 
-    // add wasi functions to linker
-    wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
+    let summary = match &args.results {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            batch::run_batch(
+                simulation,
+                inputs,
+                args.concurrency,
+                std::io::BufWriter::new(file),
+            )
+            .await?
+        }
+        None => batch::run_batch(simulation, inputs, args.concurrency, std::io::stdout()).await?,
+    };
 
-    // add wasi http to linker
-    wasmtime_wasi_http::add_to_linker_async(&mut linker)?;
+    eprintln!(
+        "{} input(s): {} ok, {} failed",
+        summary.total,
+        summary.total - summary.failed,
+        summary.failed
+    );
+    if summary.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    // add the newton provider interface to linker
-    newton::provider::http::add_to_linker::<MyCtx, HasSelf<HttpProvider>>(
-        &mut linker,
-        |ctx: &mut MyCtx| &mut ctx.http,
-    )?;
+/// Exit code when simulated operators do not reach consensus.
+const NO_CONSENSUS_EXIT: i32 = 7;
 
-    // instantiate the newton provider world
-    let newton_provider =
-        NewtonProvider::instantiate_async(&mut store, &component, &linker).await?;
+async fn consensus(args: ConsensusArgs) -> anyhow::Result<()> {
+    let fixtures = args.operator_mock.len().max(args.operator_replay.len());
+    let operators = args
+        .operators
+        .unwrap_or(if fixtures > 0 { fixtures } else { 3 });
+    if fixtures > 0 && fixtures != operators {
+        anyhow::bail!("{fixtures} operator fixture(s) given for {operators} operator(s)");
+    }
+    if operators == 0 {
+        anyhow::bail!("at least one operator is required");
+    }
 
-    // execute the run function with JSON input
-    let result = newton_provider.call_run(&mut store, input).await?;
+    let base = args.host.config(false, &args.cache)?;
+    let simulation = load_simulation(&args.wasm_file, base.clone())?;
+    let start_time = base.determinism.frozen_time.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+    });
 
-    match result {
-        Ok(output) => {
-            println!("{}", output);
-            Ok(())
+    let mut simulations = Vec::with_capacity(operators);
+    for operator in 0..operators {
+        let mut config = base.clone();
+        if let Some(path) = args.operator_mock.get(operator) {
+            config.http = HttpSource::Mock(Arc::new(MockRules::load(path)?));
         }
-        Err(error) => {
-            eprintln!("WASM execution error: {}", error);
-            std::process::exit(1);
+        if let Some(path) = args.operator_replay.get(operator) {
+            config.http = HttpSource::Replay(Cassette::load(path)?);
         }
+        if let Some(percent) = args.numeric_variance {
+            let seed = args.variance_seed.wrapping_add(operator as u64);
+            config.variance =
+                Some(NumericVariance::from_percent(percent, seed).map_err(anyhow::Error::msg)?);
+        }
+        if let Some(skew) = args.clock_skew_ms {
+            config.determinism.frozen_time =
+                Some(start_time + Duration::from_millis(skew * operator as u64));
+        }
+        simulations.push(simulation.with_config(config));
+    }
+
+    let outcomes = consensus::run_operators(simulations, &args.input_json).await;
+    let report = consensus::evaluate(&outcomes, args.quorum_threshold_percentage, args.compare);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        report.print_human();
+    }
+    if !report.consensus {
+        std::process::exit(NO_CONSENSUS_EXIT);
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+fn inspect(args: InspectArgs) -> anyhow::Result<()> {
+    let wasm_bytes = std::fs::read(&args.wasm_file)
+        .with_context(|| format!("failed to read {}", args.wasm_file.display()))?;
+    let report = inspect::inspect(&wasm_bytes, args.cache.cache().as_ref());
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        report.print_human();
+    }
+    if report.verdict == inspect::Verdict::Incompatible {
+        std::process::exit(1);
+    }
+    Ok(())
+}
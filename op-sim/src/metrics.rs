@@ -0,0 +1,93 @@
+// metrics
+// execution metrics for a single guest run: fuel, memory, host fetches and timings
+
+use crate::cache::CacheStatus;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Tracks linear memory growth so the peak can be reported after a run.
+/// Linear memories never shrink, so the peak is the sum of all granted growth.
+#[derive(Default)]
+pub struct MemoryTracker {
+    peak_memory_bytes: usize,
+}
+
+impl MemoryTracker {
+    pub fn peak_memory_bytes(&self) -> usize {
+        self.peak_memory_bytes
+    }
+
+    pub fn record_growth(&mut self, bytes: usize) {
+        self.peak_memory_bytes += bytes;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FetchMetrics {
+    pub count: usize,
+    pub total_ms: f64,
+    pub max_ms: f64,
+    pub latencies_ms: Vec<f64>,
+}
+
+impl FetchMetrics {
+    pub fn from_latencies(latencies: &[Duration]) -> Self {
+        let latencies_ms: Vec<f64> = latencies.iter().map(|d| millis(*d)).collect();
+        Self {
+            count: latencies_ms.len(),
+            total_ms: latencies_ms.iter().fold(0.0, |total, ms| total + ms),
+            max_ms: latencies_ms.iter().copied().fold(0.0, f64::max),
+            latencies_ms,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    pub fuel_budget: u64,
+    pub fuel_consumed: u64,
+    pub fuel_remaining: u64,
+    pub peak_memory_bytes: usize,
+    pub fetch: FetchMetrics,
+    pub compile_ms: f64,
+    pub component_cache: CacheStatus,
+    pub instantiate_ms: f64,
+    pub run_ms: f64,
+    pub wall_time_ms: f64,
+}
+
+impl Metrics {
+    pub fn print_human(&self) {
+        let fuel_pct = if self.fuel_budget == 0 {
+            0.0
+        } else {
+            self.fuel_consumed as f64 / self.fuel_budget as f64 * 100.0
+        };
+        eprintln!("=== op-sim metrics ===");
+        eprintln!(
+            "fuel consumed:     {} / {} ({fuel_pct:.1}%)",
+            self.fuel_consumed, self.fuel_budget
+        );
+        eprintln!("fuel remaining:    {}", self.fuel_remaining);
+        eprintln!(
+            "peak memory:       {} bytes ({:.1} MiB)",
+            self.peak_memory_bytes,
+            self.peak_memory_bytes as f64 / (1024.0 * 1024.0)
+        );
+        eprintln!(
+            "http fetches:      {} (total {:.1} ms, max {:.1} ms)",
+            self.fetch.count, self.fetch.total_ms, self.fetch.max_ms
+        );
+        eprintln!(
+            "compile time:      {:.1} ms ({})",
+            self.compile_ms, self.component_cache
+        );
+        eprintln!("instantiate time:  {:.1} ms", self.instantiate_ms);
+        eprintln!("run time:          {:.1} ms", self.run_ms);
+        eprintln!("wall time:         {:.1} ms", self.wall_time_ms);
+    }
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,376 @@
+// mock
+// serves hand-written http responses to a guest from a declarative rule file
+
+use crate::newton::provider::http::{HttpRequest, HttpResponse};
+use anyhow::{bail, Context};
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// On-disk shape of a mock file, in TOML or JSON.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockFile {
+    #[serde(default, alias = "rules")]
+    rule: Vec<RuleSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    /// HTTP method to match; any method when omitted
+    method: Option<String>,
+    /// URL glob pattern
+    url: Option<String>,
+    /// URL regular expression, used instead of `url`
+    url_regex: Option<String>,
+    /// Request body must contain this substring
+    body_contains: Option<String>,
+    /// Request body must match this regular expression
+    body_regex: Option<String>,
+    /// Request body must parse as JSON equal to this value
+    body_json: Option<serde_json::Value>,
+    response: ResponseSpec,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResponseSpec {
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Literal response body
+    body: Option<String>,
+    /// JSON response body
+    json: Option<serde_json::Value>,
+    /// Response body read from a file, relative to the mock file
+    body_file: Option<PathBuf>,
+    /// Fail the fetch with this error instead of responding
+    error: Option<String>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+pub(crate) enum UrlMatcher {
+    Any,
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl UrlMatcher {
+    /// Matcher for a rule's `url` glob or `url_regex`; matches any URL when neither is set.
+    pub(crate) fn new(url: Option<String>, url_regex: Option<String>) -> anyhow::Result<Self> {
+        Ok(match (url, url_regex) {
+            (Some(_), Some(_)) => bail!("only one of `url` and `url_regex` may be set"),
+            (Some(pattern), None) => UrlMatcher::Glob(
+                Glob::new(&pattern)
+                    .with_context(|| format!("invalid url glob `{pattern}`"))?
+                    .compile_matcher(),
+            ),
+            (None, Some(pattern)) => UrlMatcher::Regex(
+                Regex::new(&pattern).with_context(|| format!("invalid url regex `{pattern}`"))?,
+            ),
+            (None, None) => UrlMatcher::Any,
+        })
+    }
+
+    pub(crate) fn is_match(&self, url: &str) -> bool {
+        match self {
+            UrlMatcher::Any => true,
+            UrlMatcher::Glob(glob) => glob.is_match(url),
+            UrlMatcher::Regex(regex) => regex.is_match(url),
+        }
+    }
+}
+
+enum BodyMatcher {
+    Contains(String),
+    Regex(Regex),
+    Json(serde_json::Value),
+}
+
+impl BodyMatcher {
+    fn is_match(&self, body: Option<&[u8]>) -> bool {
+        let body = body.unwrap_or_default();
+        match self {
+            BodyMatcher::Contains(needle) => {
+                String::from_utf8_lossy(body).contains(needle.as_str())
+            }
+            BodyMatcher::Regex(regex) => regex.is_match(&String::from_utf8_lossy(body)),
+            BodyMatcher::Json(expected) => serde_json::from_slice::<serde_json::Value>(body)
+                .is_ok_and(|actual| &actual == expected),
+        }
+    }
+}
+
+struct Rule {
+    method: Option<String>,
+    url: UrlMatcher,
+    body: Vec<BodyMatcher>,
+    response: Result<HttpResponse, String>,
+}
+
+impl Rule {
+    fn from_spec(spec: RuleSpec, base_dir: Option<&Path>) -> anyhow::Result<Self> {
+        let url = UrlMatcher::new(spec.url, spec.url_regex)?;
+
+        let mut body = Vec::new();
+        if let Some(needle) = spec.body_contains {
+            body.push(BodyMatcher::Contains(needle));
+        }
+        if let Some(pattern) = spec.body_regex {
+            body.push(BodyMatcher::Regex(
+                Regex::new(&pattern).with_context(|| format!("invalid body regex `{pattern}`"))?,
+            ));
+        }
+        if let Some(expected) = spec.body_json {
+            body.push(BodyMatcher::Json(expected));
+        }
+
+        Ok(Self {
+            method: spec.method,
+            url,
+            body,
+            response: spec.response.into_response(base_dir)?,
+        })
+    }
+
+    fn matches(&self, request: &HttpRequest) -> bool {
+        self.method
+            .as_ref()
+            .is_none_or(|method| method.eq_ignore_ascii_case(&request.method))
+            && self.url.is_match(&request.url)
+            && self
+                .body
+                .iter()
+                .all(|matcher| matcher.is_match(request.body.as_deref()))
+    }
+}
+
+impl ResponseSpec {
+    fn into_response(
+        self,
+        base_dir: Option<&Path>,
+    ) -> anyhow::Result<Result<HttpResponse, String>> {
+        let sources = [
+            self.body.is_some(),
+            self.json.is_some(),
+            self.body_file.is_some(),
+        ];
+        if sources.iter().filter(|set| **set).count() > 1 {
+            bail!("only one of `body`, `json` and `body_file` may be set on a response");
+        }
+        if let Some(error) = self.error {
+            return Ok(Err(error));
+        }
+
+        let mut headers: Vec<(String, String)> = self.headers.into_iter().collect();
+        let body = if let Some(body) = self.body {
+            body.into_bytes()
+        } else if let Some(json) = self.json {
+            if !headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+            {
+                headers.push(("content-type".to_string(), "application/json".to_string()));
+            }
+            serde_json::to_vec(&json)?
+        } else if let Some(path) = self.body_file {
+            let Some(base_dir) = base_dir else {
+                bail!("`body_file` is not allowed here; use `body` or `json`");
+            };
+            let path = base_dir.join(path);
+            std::fs::read(&path)
+                .with_context(|| format!("failed to read body file {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Ok(HttpResponse {
+            status: self.status,
+            headers,
+            body,
+        }))
+    }
+}
+
+/// An ordered set of mock rules. The first rule matching a request answers it.
+pub struct MockRules {
+    rules: Vec<Rule>,
+}
+
+impl MockRules {
+    /// Loads a mock file. Files ending in `.json` are parsed as JSON, anything
+    /// else as TOML.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read mock file {}", path.display()))?;
+        let file: MockFile = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse mock file {}", path.display()))?
+        } else {
+            toml::from_str(&contents)
+                .with_context(|| format!("failed to parse mock file {}", path.display()))?
+        };
+
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        Self::from_file(file, Some(base_dir))
+            .with_context(|| format!("invalid mock file {}", path.display()))
+    }
+
+    /// Builds rules from the JSON form of a mock file. Relative `body_file`
+    /// paths are resolved against `base_dir`; without one, such as for rules
+    /// sent by a client, `body_file` is rejected.
+    pub fn from_json(value: serde_json::Value, base_dir: Option<&Path>) -> anyhow::Result<Self> {
+        Self::from_file(serde_json::from_value(value)?, base_dir)
+    }
+
+    fn from_file(file: MockFile, base_dir: Option<&Path>) -> anyhow::Result<Self> {
+        let rules = file
+            .rule
+            .into_iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                Rule::from_spec(spec, base_dir).with_context(|| format!("invalid rule #{}", i + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    pub fn respond(&self, request: &HttpRequest) -> Option<Result<HttpResponse, String>> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(request))
+            .map(|rule| rule.response.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(method: &str, url: &str, body: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            url: url.to_string(),
+            method: method.to_string(),
+            headers: Vec::new(),
+            body: body.map(|body| body.as_bytes().to_vec()),
+        }
+    }
+
+    fn rules(rules: serde_json::Value) -> MockRules {
+        MockRules::from_json(json!({ "rule": rules }), Some(Path::new("."))).unwrap()
+    }
+
+    fn status(rules: &MockRules, request: &HttpRequest) -> Option<u16> {
+        rules
+            .respond(request)
+            .map(|response| response.unwrap().status)
+    }
+
+    #[test]
+    fn first_matching_rule_answers() {
+        let rules = rules(json!([
+            { "method": "POST", "url": "https://api.example.com/*", "response": { "status": 201 } },
+            { "url": "https://api.example.com/*", "response": { "status": 200 } },
+            { "response": { "status": 404 } },
+        ]));
+        let post = request("post", "https://api.example.com/items", None);
+        let get = request("GET", "https://api.example.com/items", None);
+        let other = request("GET", "https://other.example.com/", None);
+        assert_eq!(status(&rules, &post), Some(201));
+        assert_eq!(status(&rules, &get), Some(200));
+        assert_eq!(status(&rules, &other), Some(404));
+    }
+
+    #[test]
+    fn unmatched_requests_get_no_response() {
+        let rules = rules(json!([
+            { "url_regex": "^https://api\\.example\\.com/v[0-9]+/price$", "response": {} },
+        ]));
+        let matched = request("GET", "https://api.example.com/v2/price", None);
+        let unmatched = request("GET", "https://api.example.com/v2/price?x=1", None);
+        assert_eq!(status(&rules, &matched), Some(200));
+        assert!(rules.respond(&unmatched).is_none());
+    }
+
+    #[test]
+    fn body_matchers_must_all_match() {
+        let rules = rules(json!([
+            {
+                "body_contains": "eth_call",
+                "body_regex": "\"id\":\\s*1",
+                "response": { "status": 200 },
+            },
+            { "body_json": { "a": [1, 2] }, "response": { "status": 202 } },
+        ]));
+        let both = request(
+            "POST",
+            "http://rpc",
+            Some(r#"{"method":"eth_call","id": 1}"#),
+        );
+        let one = request(
+            "POST",
+            "http://rpc",
+            Some(r#"{"method":"eth_call","id":2}"#),
+        );
+        let json = request("POST", "http://rpc", Some(r#"{ "a": [1,2] }"#));
+        let none = request("POST", "http://rpc", None);
+        assert_eq!(status(&rules, &both), Some(200));
+        assert!(rules.respond(&one).is_none());
+        assert_eq!(status(&rules, &json), Some(202));
+        assert!(rules.respond(&none).is_none());
+    }
+
+    #[test]
+    fn builds_json_and_error_responses() {
+        let rules = rules(json!([
+            { "url": "*/fail", "response": { "error": "connection reset" } },
+            { "response": { "json": { "price": 1.5 } } },
+        ]));
+        let failed = rules
+            .respond(&request("GET", "http://x/fail", None))
+            .unwrap();
+        assert_eq!(failed.unwrap_err(), "connection reset");
+        let response = rules
+            .respond(&request("GET", "http://x/price", None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.body, br#"{"price":1.5}"#);
+        assert!(response
+            .headers
+            .contains(&("content-type".to_string(), "application/json".to_string())));
+    }
+
+    #[test]
+    fn body_files_need_a_base_dir() {
+        let rule = json!({ "rule": [{ "response": { "body_file": "Cargo.toml" } }] });
+        let error = MockRules::from_json(rule.clone(), None).err().unwrap();
+        assert!(format!("{error:#}").contains("`body_file` is not allowed here"));
+        let rules = MockRules::from_json(rule, Some(Path::new("."))).unwrap();
+        let response = rules.respond(&request("GET", "http://x/", None)).unwrap();
+        assert!(response.unwrap().body.starts_with(b"[package]"));
+    }
+
+    #[test]
+    fn rejects_conflicting_rules() {
+        for rule in [
+            json!({ "url": "*", "url_regex": ".*", "response": {} }),
+            json!({ "response": { "body": "a", "json": {} } }),
+            json!({ "url_regex": "(", "response": {} }),
+            json!({ "response": {}, "unknown": true }),
+        ] {
+            assert!(
+                MockRules::from_json(json!({ "rule": [rule] }), Some(Path::new("."))).is_err(),
+                "{rule}"
+            );
+        }
+    }
+}
@@ -0,0 +1,490 @@
+// network
+// outbound network policy applied to guest fetches, wasi:http requests and wasi:sockets
+
+use globset::{Glob, GlobMatcher};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use std::error::Error;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    HostFutureIncomingResponse, IncomingResponse, OutgoingRequestConfig,
+};
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct NetworkArgs {
+    /// Only allow requests to this host (repeatable, supports globs like *.example.com)
+    #[arg(long = "allow-host", value_name = "HOST")]
+    pub allow_hosts: Vec<String>,
+    /// Only allow requests with this URL scheme (repeatable)
+    #[arg(long = "allow-scheme", value_name = "SCHEME", default_values = ["http", "https"])]
+    pub allow_schemes: Vec<String>,
+    /// Timeout for each HTTP request, in milliseconds
+    #[arg(long, value_name = "MS")]
+    pub request_timeout_ms: Option<u64>,
+    /// Time budget for all HTTP requests of a run, in milliseconds from the start of the run
+    #[arg(long, value_name = "MS")]
+    pub total_timeout_ms: Option<u64>,
+    /// Reject responses whose body is larger than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_response_bytes: Option<usize>,
+    /// Maximum number of redirects to follow
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub max_redirects: usize,
+    /// Reject requests to loopback, private, link-local and other non-public addresses
+    #[arg(long)]
+    pub block_private_ips: bool,
+}
+
+pub struct NetworkPolicy {
+    allowed_hosts: Vec<GlobMatcher>,
+    allowed_schemes: Vec<String>,
+    request_timeout: Option<Duration>,
+    total_timeout: Option<Duration>,
+    max_response_bytes: Option<usize>,
+    max_redirects: usize,
+    block_private_ips: bool,
+}
+
+impl Default for NetworkPolicy {
+    /// The policy the CLI applies when no network flags are given.
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            request_timeout: None,
+            total_timeout: None,
+            max_response_bytes: None,
+            max_redirects: 10,
+            block_private_ips: false,
+        }
+    }
+}
+
+impl NetworkPolicy {
+    pub fn from_args(args: &NetworkArgs) -> anyhow::Result<Self> {
+        let allowed_hosts = args
+            .allow_hosts
+            .iter()
+            .map(|host| Ok(Glob::new(&host.to_ascii_lowercase())?.compile_matcher()))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            allowed_hosts,
+            allowed_schemes: args
+                .allow_schemes
+                .iter()
+                .map(|s| s.to_ascii_lowercase())
+                .collect(),
+            request_timeout: args.request_timeout_ms.map(Duration::from_millis),
+            total_timeout: args.total_timeout_ms.map(Duration::from_millis),
+            max_response_bytes: args.max_response_bytes,
+            max_redirects: args.max_redirects,
+            block_private_ips: args.block_private_ips,
+        })
+    }
+
+    /// Checks the scheme and host of a URL, and the address when the host is
+    /// an IP literal. The addresses a hostname resolves to are only checked
+    /// by the clients from [`NetworkPolicy::client`] and
+    /// [`NetworkPolicy::wasi_http_client`], which resolve it themselves.
+    pub fn check_url(&self, url: &reqwest::Url) -> Result<(), String> {
+        if !self
+            .allowed_schemes
+            .iter()
+            .any(|scheme| scheme == url.scheme())
+        {
+            return Err(format!(
+                "scheme `{}` is not allowed by the network policy",
+                url.scheme()
+            ));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("URL has no host: {url}"))?;
+        self.check_host(host.trim_start_matches('[').trim_end_matches(']'))
+    }
+
+    fn check_host(&self, host: &str) -> Result<(), String> {
+        let host = host.to_ascii_lowercase();
+        if !self.allowed_hosts.is_empty()
+            && !self.allowed_hosts.iter().any(|glob| glob.is_match(&host))
+        {
+            return Err(format!(
+                "host `{host}` is not allowed by the network policy"
+            ));
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            self.check_ip(ip)?;
+        }
+        Ok(())
+    }
+
+    fn check_ip(&self, ip: IpAddr) -> Result<(), String> {
+        if self.block_private_ips && !is_public(ip) {
+            return Err(format!("address {ip} is blocked by the network policy"));
+        }
+        Ok(())
+    }
+
+    /// Builds the reqwest client used for guest fetches, enforcing redirect
+    /// limits, the host allowlist on redirects and private address blocking.
+    pub fn client(self: &Arc<Self>) -> reqwest::Result<reqwest::Client> {
+        let policy = Arc::clone(self);
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > policy.max_redirects {
+                attempt.error(format!("more than {} redirects", policy.max_redirects))
+            } else if let Err(e) = policy.check_url(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        });
+        self.client_builder().redirect(redirect).build()
+    }
+
+    /// Builds the reqwest client used for guest wasi:http requests. wasi:http
+    /// hands redirects back to the guest, so none are followed.
+    pub fn wasi_http_client(&self) -> reqwest::Result<reqwest::Client> {
+        self.client_builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+    }
+
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder();
+        if self.block_private_ips {
+            builder.dns_resolver(Arc::new(PublicOnlyResolver))
+        } else {
+            builder
+        }
+    }
+
+    /// Timeout for the next request given when the run started, or an error
+    /// once the total budget is spent.
+    pub fn next_timeout(&self, run_started: Instant) -> Result<Option<Duration>, String> {
+        let remaining = match self.total_timeout {
+            Some(total) => match total.checked_sub(run_started.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err("total HTTP time budget exhausted".to_string()),
+            },
+            None => None,
+        };
+        Ok(match (self.request_timeout, remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    pub fn max_response_bytes(&self) -> Option<usize> {
+        self.max_response_bytes
+    }
+
+    /// Checks an outgoing wasi:http request and sends it with `client`, from
+    /// [`NetworkPolicy::wasi_http_client`], so that it resolves hostnames the
+    /// way guest fetches do. The request timeout and the run's total budget
+    /// cover the whole exchange, body included, and the body is cut off past
+    /// the response size cap. A request the policy rejects outright is an
+    /// error carrying the reason.
+    pub fn send_wasi_http(
+        &self,
+        client: &reqwest::Client,
+        run_started: Instant,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> Result<HostFutureIncomingResponse, String> {
+        let uri = request.uri().to_string();
+        let url = reqwest::Url::parse(&uri).map_err(|e| format!("invalid URL `{uri}`: {e}"))?;
+        self.check_url(&url)?;
+        let timeout = self.next_timeout(run_started)?;
+        let mut request = reqwest::Request::try_from(request.map(reqwest::Body::wrap))
+            .map_err(|e| format!("invalid request to `{uri}`: {e}"))?;
+        *request.timeout_mut() = timeout;
+
+        let client = client.clone();
+        let max_bytes = self.max_response_bytes;
+        let handle = wasmtime_wasi::runtime::spawn(async move {
+            Ok(send_wasi_http(client, request, max_bytes, config).await)
+        });
+        Ok(HostFutureIncomingResponse::pending(handle))
+    }
+
+    /// Restricts raw wasi:sockets access. Sockets bypass the host allowlist, so
+    /// they are denied entirely when one is configured.
+    pub fn configure_wasi(self: &Arc<Self>, builder: &mut wasmtime_wasi::p2::WasiCtxBuilder) {
+        let policy = Arc::clone(self);
+        builder.socket_addr_check(move |addr: SocketAddr, _| {
+            let allowed = policy.allowed_hosts.is_empty() && policy.check_ip(addr.ip()).is_ok();
+            Box::pin(async move { allowed })
+        });
+    }
+}
+
+/// Resolves hostnames with the system resolver and drops every non-public
+/// address, failing if none are left.
+struct PublicOnlyResolver;
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(Box::new(BlockedHost(host)) as Box<dyn Error + Send + Sync>);
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        }) as Pin<Box<dyn Future<Output = _> + Send>>
+    }
+}
+
+#[derive(Debug)]
+struct BlockedHost(String);
+
+impl std::fmt::Display for BlockedHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "host `{}` resolves only to addresses blocked by the network policy",
+            self.0
+        )
+    }
+}
+
+impl Error for BlockedHost {}
+
+async fn send_wasi_http(
+    client: reqwest::Client,
+    request: reqwest::Request,
+    max_bytes: Option<usize>,
+    config: OutgoingRequestConfig,
+) -> Result<IncomingResponse, ErrorCode> {
+    // the guest's own timeouts still bound the wait for the response head
+    let response = tokio::time::timeout(
+        config.connect_timeout + config.first_byte_timeout,
+        client.execute(request),
+    )
+    .await
+    .map_err(|_| ErrorCode::ConnectionTimeout)?
+    .map_err(|e| request_error(&e))?;
+
+    let limit = max_bytes.unwrap_or(usize::MAX);
+    if let Some(len) = response.content_length().filter(|len| *len > limit as u64) {
+        return Err(ErrorCode::HttpResponseBodySize(Some(len)));
+    }
+    let resp = hyper::Response::from(response).map(|body| {
+        Limited::new(body, limit)
+            .map_err(move |e| {
+                if e.is::<LengthLimitError>() {
+                    ErrorCode::HttpResponseBodySize(Some(limit as u64))
+                } else if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+                    response_error(e)
+                } else {
+                    ErrorCode::InternalError(Some(e.to_string()))
+                }
+            })
+            .boxed()
+    });
+    Ok(IncomingResponse {
+        resp,
+        worker: None,
+        between_bytes_timeout: config.between_bytes_timeout,
+    })
+}
+
+fn request_error(error: &reqwest::Error) -> ErrorCode {
+    let mut source = error.source();
+    while let Some(cause) = source {
+        if cause.is::<BlockedHost>() {
+            return ErrorCode::DestinationIpProhibited;
+        }
+        source = cause.source();
+    }
+    if error.is_timeout() {
+        ErrorCode::ConnectionTimeout
+    } else if error.is_connect() {
+        ErrorCode::ConnectionRefused
+    } else {
+        ErrorCode::InternalError(Some(describe(error)))
+    }
+}
+
+fn response_error(error: &reqwest::Error) -> ErrorCode {
+    if error.is_timeout() {
+        ErrorCode::ConnectionReadTimeout
+    } else {
+        ErrorCode::InternalError(Some(describe(error)))
+    }
+}
+
+// an error and its sources, outermost first
+fn describe(error: &dyn Error) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        description.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    description
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // shared address space (100.64.0.0/10)
+                || (a == 100 && (64..128).contains(&b))
+                // benchmarking (198.18.0.0/15)
+                || (a == 198 && (18..20).contains(&b))
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local (fc00::/7)
+                || (first & 0xfe00) == 0xfc00
+                // link-local (fe80::/10)
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn public_addresses() {
+        for address in [
+            "8.8.8.8",
+            "1.1.1.1",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public(ip(address)), "{address}");
+        }
+    }
+
+    #[test]
+    fn private_and_special_ipv4_addresses() {
+        for address in [
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "127.0.0.1",
+            "0.0.0.0",
+            "0.1.2.3",
+            "255.255.255.255",
+            "240.0.0.1",
+            "224.0.0.1",
+            "192.0.2.1",
+            // link-local, including cloud metadata endpoints
+            "169.254.169.254",
+            // CGNAT shared address space
+            "100.64.0.1",
+            "100.127.255.255",
+            // benchmarking
+            "198.18.0.1",
+        ] {
+            assert!(!is_public(ip(address)), "{address}");
+        }
+    }
+
+    #[test]
+    fn private_and_special_ipv6_addresses() {
+        for address in [
+            "::1",
+            "::",
+            "fe80::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "ff02::1",
+            // IPv4-mapped addresses are checked as IPv4
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::ffff:169.254.169.254",
+            "::ffff:100.64.0.1",
+        ] {
+            assert!(!is_public(ip(address)), "{address}");
+        }
+    }
+
+    fn policy(args: &[&str]) -> NetworkPolicy {
+        use clap::Parser;
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            network: NetworkArgs,
+        }
+        let cli = Cli::parse_from(std::iter::once("op-sim").chain(args.iter().copied()));
+        NetworkPolicy::from_args(&cli.network).unwrap()
+    }
+
+    fn check(policy: &NetworkPolicy, url: &str) -> Result<(), String> {
+        policy.check_url(&reqwest::Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn checks_schemes_and_allowed_hosts() {
+        let policy = policy(&["--allow-host", "*.example.com", "--allow-scheme", "https"]);
+        assert!(check(&policy, "https://api.example.com/v1").is_ok());
+        assert!(check(&policy, "https://API.Example.com/v1").is_ok());
+        assert!(check(&policy, "http://api.example.com/v1").is_err());
+        assert!(check(&policy, "https://example.org/").is_err());
+    }
+
+    #[test]
+    fn blocks_private_ip_literals() {
+        let policy = policy(&["--block-private-ips"]);
+        assert!(check(&policy, "http://8.8.8.8/").is_ok());
+        assert!(check(&policy, "http://127.0.0.1:8080/").is_err());
+        assert!(check(&policy, "http://[::1]/").is_err());
+        assert!(check(&policy, "http://[::ffff:169.254.169.254]/").is_err());
+        // hostnames are checked once resolved, by the clients
+        assert!(check(&policy, "http://localhost/").is_ok());
+    }
+
+    #[tokio::test]
+    async fn wasi_http_client_blocks_hostnames_resolving_to_private_addresses() {
+        let client = policy(&["--block-private-ips"]).wasi_http_client().unwrap();
+        let error = client.get("http://localhost:9/").send().await.unwrap_err();
+        assert!(matches!(
+            request_error(&error),
+            ErrorCode::DestinationIpProhibited
+        ));
+    }
+
+    #[test]
+    fn request_timeouts_come_out_of_the_total_budget() {
+        let budgeted = policy(&["--request-timeout-ms", "500", "--total-timeout-ms", "60000"]);
+        let timeout = budgeted.next_timeout(Instant::now()).unwrap().unwrap();
+        assert_eq!(timeout, Duration::from_millis(500));
+
+        let spent = policy(&["--total-timeout-ms", "10"]);
+        let started = Instant::now() - Duration::from_millis(20);
+        assert!(spent.next_timeout(started).is_err());
+    }
+}
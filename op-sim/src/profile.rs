@@ -0,0 +1,118 @@
+// profile
+// samples guest stacks during a run into a Firefox profiler file or folded stacks for flamegraphs
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use wasmtime::component::Component;
+use wasmtime::{AsContext, GuestProfiler, WasmBacktrace};
+
+/// How often guest stacks are sampled while profiling. The engine's epoch
+/// advances at this rate instead of [`crate::limits::EPOCH_TICK`].
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Per-run sampler, called from the store's epoch callback.
+pub struct Profiler {
+    guest: GuestProfiler,
+    // folded stack ("root;...;leaf") to sample count
+    stacks: BTreeMap<String, u64>,
+    last_sample: Instant,
+    samples: u64,
+}
+
+impl Profiler {
+    pub fn new(component: &Component) -> Self {
+        Self {
+            guest: GuestProfiler::new_component("guest", SAMPLE_INTERVAL, component.clone(), []),
+            stacks: BTreeMap::new(),
+            last_sample: Instant::now(),
+            samples: 0,
+        }
+    }
+
+    /// Records the guest stack at this point of the run.
+    pub fn sample(&mut self, store: impl AsContext) {
+        let now = Instant::now();
+        self.guest.sample(&store, now - self.last_sample);
+        self.last_sample = now;
+
+        let backtrace = WasmBacktrace::capture(&store);
+        if backtrace.frames().is_empty() {
+            return;
+        }
+        let stack = backtrace
+            .frames()
+            .iter()
+            .rev()
+            .map(|frame| {
+                let module = frame.module().name().unwrap_or("<unknown>");
+                match frame.func_name() {
+                    Some(name) => format!("{module}`{}", demangle(name)),
+                    None => format!("{module}`func[{}]", frame.func_index()),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        *self.stacks.entry(stack).or_default() += 1;
+        self.samples += 1;
+    }
+
+    pub fn finish(self) -> anyhow::Result<Profile> {
+        let mut firefox = Vec::new();
+        self.guest.finish(&mut firefox)?;
+        Ok(Profile {
+            samples: self.samples,
+            firefox,
+            folded: self.stacks,
+        })
+    }
+}
+
+/// The samples of one run.
+pub struct Profile {
+    pub samples: u64,
+    /// Firefox profiler "processed profile" JSON
+    pub firefox: Vec<u8>,
+    folded: BTreeMap<String, u64>,
+}
+
+impl Profile {
+    /// Folded stacks, one `frame;frame;frame count` line per distinct stack,
+    /// as read by flamegraph.pl, inferno and speedscope.
+    pub fn folded(&self) -> String {
+        self.folded
+            .iter()
+            .map(|(stack, count)| format!("{stack} {count}\n"))
+            .collect()
+    }
+
+    /// Writes folded stacks to files ending in `.folded` or `.txt`, and a
+    /// Firefox profiler file to anything else.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = if path
+            .extension()
+            .is_some_and(|ext| ext == "folded" || ext == "txt")
+        {
+            self.folded().into_bytes()
+        } else {
+            self.firefox.clone()
+        };
+        std::fs::write(path, contents)
+            .with_context(|| format!("failed to write profile {}", path.display()))
+    }
+}
+
+// demangles Rust and C++ symbols, as in wasmtime's own backtraces and in the
+// Firefox profile, keeping anything else as it is
+fn demangle(name: &str) -> String {
+    if let Ok(symbol) = rustc_demangle::try_demangle(name) {
+        return format!("{symbol:#}");
+    }
+    if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+        if let Ok(demangled) = symbol.demangle(&Default::default()) {
+            return demangled;
+        }
+    }
+    name.to_string()
+}
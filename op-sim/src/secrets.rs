@@ -0,0 +1,184 @@
+// secrets
+// resolves secret ids requested by a guest from a local file or environment variables
+
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Clone)]
+pub struct SecretsProvider {
+    values: HashMap<String, String>,
+    // when set, secrets missing from the file are looked up as `<prefix><SECRET_ID>`
+    env_prefix: Option<String>,
+    // fail the lookup instead of returning an empty string
+    strict: bool,
+    // secret ids the guest asked for that could not be resolved
+    missing: Vec<String>,
+    // values resolved from the environment during the run
+    resolved_env: Vec<String>,
+}
+
+impl SecretsProvider {
+    pub fn new(env_prefix: Option<String>, strict: bool) -> Self {
+        Self {
+            values: HashMap::new(),
+            env_prefix,
+            strict,
+            missing: Vec::new(),
+            resolved_env: Vec::new(),
+        }
+    }
+
+    /// Loads secrets from a flat object of id to value. Files ending in `.json`
+    /// are parsed as JSON, anything else as TOML.
+    pub fn load_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read secrets file {}", path.display()))?;
+        let values: HashMap<String, String> = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents)
+                .with_context(|| format!("failed to parse secrets file {}", path.display()))?
+        } else {
+            toml::from_str(&contents)
+                .with_context(|| format!("failed to parse secrets file {}", path.display()))?
+        };
+        self.values.extend(values);
+        Ok(())
+    }
+
+    /// Adds secrets on top of any already loaded, replacing values with the same id.
+    pub fn extend(&mut self, values: impl IntoIterator<Item = (String, String)>) {
+        self.values.extend(values);
+    }
+
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    /// Every secret value that could have reached the guest: those loaded
+    /// from files and those resolved from the environment so far.
+    pub fn values(&self) -> impl Iterator<Item = &str> {
+        self.values
+            .values()
+            .chain(&self.resolved_env)
+            .map(String::as_str)
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    fn resolve(&mut self, secret_id: &str) -> Option<String> {
+        if let Some(value) = self.values.get(secret_id) {
+            return Some(value.clone());
+        }
+        let prefix = self.env_prefix.as_ref()?;
+        let value = std::env::var(env_var_name(prefix, secret_id)).ok()?;
+        self.resolved_env.push(value.clone());
+        Some(value)
+    }
+}
+
+/// Maps a secret id to an environment variable name: uppercased, with every
+/// character that is not alphanumeric replaced by `_`.
+pub fn env_var_name(prefix: &str, secret_id: &str) -> String {
+    let id: String = secret_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{prefix}{id}")
+}
+
+impl crate::newton::provider::secrets::Host for SecretsProvider {
+    async fn get(&mut self, secret_id: String) -> Result<String, String> {
+        match self.resolve(&secret_id) {
+            Some(value) => Ok(value),
+            None => {
+                self.missing.push(secret_id.clone());
+                if self.strict {
+                    Err(format!("secret not found: {secret_id}"))
+                } else {
+                    Ok(String::new())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::newton::provider::secrets::Host;
+
+    #[test]
+    fn env_var_names_are_uppercased_with_underscores() {
+        assert_eq!(
+            env_var_name("NEWTON_SECRET_", "farcaster-api-key"),
+            "NEWTON_SECRET_FARCASTER_API_KEY"
+        );
+        assert_eq!(env_var_name("", "a.b/c9"), "A_B_C9");
+        assert_eq!(env_var_name("P_", "clé"), "P_CL_");
+    }
+
+    #[tokio::test]
+    async fn file_values_take_precedence_over_the_environment() {
+        std::env::set_var("OP_SIM_TEST_PRECEDENCE_API_KEY", "from-env");
+        std::env::set_var("OP_SIM_TEST_PRECEDENCE_OTHER", "env-only");
+        let mut secrets = SecretsProvider::new(Some("OP_SIM_TEST_PRECEDENCE_".to_string()), false);
+        secrets.extend([("api-key".to_string(), "from-file".to_string())]);
+
+        assert_eq!(
+            secrets.get("api-key".to_string()).await.unwrap(),
+            "from-file"
+        );
+        assert_eq!(secrets.get("other".to_string()).await.unwrap(), "env-only");
+        let mut values: Vec<&str> = secrets.values().collect();
+        values.sort();
+        assert_eq!(values, ["env-only", "from-file"]);
+        assert!(secrets.missing().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unresolved_ids_are_tracked_and_read_as_empty() {
+        let mut secrets = SecretsProvider::new(None, false);
+        assert_eq!(secrets.get("absent".to_string()).await.unwrap(), "");
+        assert_eq!(secrets.get("also-absent".to_string()).await.unwrap(), "");
+        assert_eq!(secrets.missing(), ["absent", "also-absent"]);
+    }
+
+    #[tokio::test]
+    async fn strict_mode_fails_unresolved_lookups() {
+        let mut secrets = SecretsProvider::new(Some("OP_SIM_TEST_STRICT_UNSET_".to_string()), true);
+        secrets.extend([("known".to_string(), "value".to_string())]);
+        assert_eq!(secrets.get("known".to_string()).await.unwrap(), "value");
+        assert_eq!(
+            secrets.get("unknown".to_string()).await.unwrap_err(),
+            "secret not found: unknown"
+        );
+        assert_eq!(secrets.missing(), ["unknown"]);
+    }
+
+    #[test]
+    fn loads_toml_and_json_files() {
+        let dir = std::env::temp_dir().join(format!("op-sim-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml = dir.join("secrets.toml");
+        let json = dir.join("secrets.json");
+        std::fs::write(&toml, "a = \"1\"\nb = \"2\"\n").unwrap();
+        std::fs::write(&json, r#"{"b": "3"}"#).unwrap();
+
+        let mut secrets = SecretsProvider::new(None, false);
+        secrets.load_file(&toml).unwrap();
+        secrets.load_file(&json).unwrap();
+        assert_eq!(secrets.resolve("a").as_deref(), Some("1"));
+        assert_eq!(secrets.resolve("b").as_deref(), Some("3"));
+        std::fs::write(&json, r#"{"b": 3}"#).unwrap();
+        assert!(secrets.load_file(&json).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
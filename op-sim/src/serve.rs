@@ -0,0 +1,539 @@
+// serve
+// local JSON-RPC endpoint over HTTP that runs components on a warm engine
+
+use crate::cassette::Cassette;
+use crate::envelope::Envelope;
+use crate::http::HttpSource;
+use crate::inspect;
+use crate::metrics::Metrics;
+use crate::mock::MockRules;
+use crate::sim::{GuestOutput, Simulation, Simulator, SimulatorConfig};
+use crate::worlds::{WorldVersion, WORLDS};
+use anyhow::Context;
+use base64::Engine as _;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderMap, CONTENT_TYPE, HOST, ORIGIN};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// upper bound on a request body, which may carry a base64-encoded component
+const MAX_REQUEST_BYTES: usize = 64 * 1024 * 1024;
+
+// number of compiled components kept in memory before the oldest are dropped
+const MAX_COMPILED: usize = 16;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: Option<String>,
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn invalid_params(error: impl std::fmt::Display) -> Self {
+        Self::new(INVALID_PARAMS, format!("invalid params: {error:#}"))
+    }
+
+    fn server(error: anyhow::Error) -> Self {
+        Self::new(SERVER_ERROR, format!("{error:#}"))
+    }
+}
+
+/// The component to run: a path on the server's filesystem or the bytes
+/// themselves, base64-encoded.
+#[derive(Debug, Deserialize)]
+struct WasmSource {
+    wasm_path: Option<PathBuf>,
+    wasm_base64: Option<String>,
+}
+
+impl WasmSource {
+    fn read(&self) -> Result<Vec<u8>, RpcError> {
+        match (&self.wasm_path, &self.wasm_base64) {
+            (Some(path), None) => std::fs::read(path)
+                .with_context(|| format!("failed to read {}", path.display()))
+                .map_err(RpcError::server),
+            (None, Some(encoded)) => base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| RpcError::invalid_params(format!("wasm_base64: {e}"))),
+            _ => Err(RpcError::invalid_params(
+                "exactly one of `wasm_path` and `wasm_base64` is required",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SimulateParams {
+    #[serde(flatten)]
+    wasm: WasmSource,
+    input: String,
+    /// path to a mock rules file
+    mock: Option<PathBuf>,
+    /// mock rules inline, in the JSON form of a mock file
+    mock_rules: Option<Value>,
+    /// path to a cassette to replay
+    replay: Option<PathBuf>,
+    /// cassette to replay, inline
+    cassette: Option<Cassette>,
+    /// secrets for this request, on top of the server's
+    #[serde(default)]
+    secrets: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InspectParams {
+    #[serde(flatten)]
+    wasm: WasmSource,
+}
+
+#[derive(Debug, Serialize)]
+struct SimulateResult {
+    #[serde(flatten)]
+    envelope: Envelope,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics: Option<Metrics>,
+}
+
+/// Serves `simulate`, `inspect` and `version` calls. The engine is created
+/// once, and compiled components are kept in memory by content hash so
+/// repeated calls with the same WASM skip compilation.
+pub struct Server {
+    simulator: Simulator,
+    compiled: Mutex<Vec<(String, Arc<Simulation>)>>,
+}
+
+impl Server {
+    /// `config` is the default host configuration; requests may override the
+    /// HTTP source and add secrets.
+    pub fn new(config: SimulatorConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            simulator: Simulator::new(config)?,
+            compiled: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Handles a JSON-RPC request body, single or batch. Returns `None` when
+    /// there is nothing to respond with (only notifications).
+    pub async fn handle(self: &Arc<Self>, body: &[u8]) -> Option<Value> {
+        let request: Value = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                ))
+            }
+        };
+        match request {
+            Value::Array(requests) if requests.is_empty() => Some(error_response(
+                Value::Null,
+                RpcError::new(INVALID_REQUEST, "empty batch"),
+            )),
+            Value::Array(requests) => {
+                let mut responses = Vec::new();
+                for request in requests {
+                    responses.extend(self.handle_one(request).await);
+                }
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            request => self.handle_one(request).await,
+        }
+    }
+
+    async fn handle_one(self: &Arc<Self>, request: Value) -> Option<Value> {
+        let request: RpcRequest = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(INVALID_REQUEST, e.to_string()),
+                ))
+            }
+        };
+        if request.jsonrpc.as_deref() != Some("2.0") {
+            let id = request.id.unwrap_or(Value::Null);
+            return Some(error_response(
+                id,
+                RpcError::new(INVALID_REQUEST, "`jsonrpc` must be \"2.0\""),
+            ));
+        }
+
+        let result = self.call(&request.method, request.params).await;
+        // requests without an id are notifications and get no response
+        let id = request.id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_response(id, error),
+        })
+    }
+
+    async fn call(self: &Arc<Self>, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "simulate" => {
+                let params = serde_json::from_value(params).map_err(RpcError::invalid_params)?;
+                self.simulate(params).await
+            }
+            "inspect" => {
+                let params: InspectParams =
+                    serde_json::from_value(params).map_err(RpcError::invalid_params)?;
+                let wasm_bytes = params.wasm.read()?;
+                let server = Arc::clone(self);
+                let report = tokio::task::spawn_blocking(move || {
+                    inspect::inspect(&wasm_bytes, server.simulator.config().cache.as_ref())
+                })
+                .await
+                .map_err(|e| RpcError::server(e.into()))?;
+                serde_json::to_value(report).map_err(|e| RpcError::server(e.into()))
+            }
+            "version" => Ok(json!({
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "world": WorldVersion::current().name(),
+                "worlds": WORLDS
+                    .iter()
+                    .map(|world| json!({ "world": world.name(), "operator_support": world.operator_support }))
+                    .collect::<Vec<_>>(),
+            })),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method `{method}`"),
+            )),
+        }
+    }
+
+    async fn simulate(self: &Arc<Self>, params: SimulateParams) -> Result<Value, RpcError> {
+        let mut config = self.simulator.config().clone();
+        let sources = [
+            params.mock.is_some(),
+            params.mock_rules.is_some(),
+            params.replay.is_some(),
+            params.cassette.is_some(),
+        ];
+        if sources.iter().filter(|set| **set).count() > 1 {
+            return Err(RpcError::invalid_params(
+                "only one of `mock`, `mock_rules`, `replay` and `cassette` may be set",
+            ));
+        }
+        if let Some(path) = &params.mock {
+            config.http =
+                HttpSource::Mock(Arc::new(MockRules::load(path).map_err(RpcError::server)?));
+        }
+        if let Some(rules) = params.mock_rules {
+            let rules = MockRules::from_json(rules, None).map_err(RpcError::invalid_params)?;
+            config.http = HttpSource::Mock(Arc::new(rules));
+        }
+        if let Some(path) = &params.replay {
+            config.http = HttpSource::Replay(Cassette::load(path).map_err(RpcError::server)?);
+        }
+        if let Some(cassette) = params.cassette {
+            config.http = HttpSource::Replay(cassette);
+        }
+        config.secrets.extend(params.secrets);
+
+        let wasm_bytes = params.wasm.read()?;
+        let server = Arc::clone(self);
+        let simulation = tokio::task::spawn_blocking(move || server.compiled(&wasm_bytes))
+            .await
+            .map_err(|e| RpcError::server(e.into()))?
+            .map_err(RpcError::server)?
+            .with_config(config);
+
+        let result = match simulation.run(&params.input, &GuestOutput::capture()).await {
+            Ok(run) => {
+                let mut envelope = Envelope::new(
+                    &run.outcome,
+                    &run.guest_stdout,
+                    &run.guest_stderr,
+                    run.timings,
+                );
+                envelope.faults = run.http.applied_faults().to_vec();
+                envelope.logs = run.logs;
+                envelope.logs_dropped = run.logs_dropped;
+                SimulateResult {
+                    envelope,
+                    metrics: Some(run.metrics),
+                }
+            }
+            Err(e) => SimulateResult {
+                envelope: Envelope::new(&Err(e), &[], &[], Default::default()),
+                metrics: None,
+            },
+        };
+        serde_json::to_value(result).map_err(|e| RpcError::server(e.into()))
+    }
+
+    /// Returns the compiled component for these bytes, compiling it on first use.
+    fn compiled(&self, wasm_bytes: &[u8]) -> anyhow::Result<Arc<Simulation>> {
+        let key = hex::encode(Sha256::digest(wasm_bytes));
+        if let Some((_, simulation)) = self.lock_compiled().iter().find(|(k, _)| *k == key) {
+            return Ok(Arc::clone(simulation));
+        }
+
+        let simulation = Arc::new(self.simulator.load(wasm_bytes)?);
+        let mut compiled = self.lock_compiled();
+        if compiled.len() >= MAX_COMPILED {
+            compiled.remove(0);
+        }
+        compiled.push((key, Arc::clone(&simulation)));
+        Ok(simulation)
+    }
+
+    fn lock_compiled(&self) -> std::sync::MutexGuard<'_, Vec<(String, Arc<Simulation>)>> {
+        self.compiled
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    async fn respond<B>(
+        self: &Arc<Self>,
+        request: Request<B>,
+        local_addr: SocketAddr,
+    ) -> Response<Full<Bytes>>
+    where
+        B: Body,
+        B::Error: std::error::Error + Send + Sync + 'static,
+    {
+        if request.method() != Method::POST {
+            return text_response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "op-sim expects JSON-RPC requests over POST\n",
+            );
+        }
+        if let Err((status, reason)) = check_headers(request.headers(), local_addr) {
+            return text_response(status, &reason);
+        }
+        let body = match Limited::new(request.into_body(), MAX_REQUEST_BYTES)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return text_response(StatusCode::PAYLOAD_TOO_LARGE, &format!("{e}\n")),
+        };
+        match self.handle(&body).await {
+            Some(response) => Response::builder()
+                .header("content-type", "application/json")
+                .body(Full::new(Bytes::from(response.to_string())))
+                .expect("response is valid"),
+            None => Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Full::default())
+                .expect("response is valid"),
+        }
+    }
+}
+
+// Keeps web pages away from the server, which runs whatever WASM it is sent
+// with the host's secrets and network. Browsers send cross-origin text/plain
+// POSTs without a preflight, so only application/json bodies are accepted;
+// they also attach an Origin to cross-origin POSTs, which no local client
+// needs. Checking Host against the listening address stops a rebound DNS
+// name from reaching the server as if it were same-origin.
+fn check_headers(headers: &HeaderMap, local_addr: SocketAddr) -> Result<(), (StatusCode, String)> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("application/json") {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "op-sim expects a Content-Type of application/json\n".to_string(),
+        ));
+    }
+    if headers.contains_key(ORIGIN) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "op-sim does not accept requests from web pages\n".to_string(),
+        ));
+    }
+    let host = headers
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if !host_allowed(host, local_addr) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("op-sim expects a Host of {local_addr}, not {host:?}\n"),
+        ));
+    }
+    Ok(())
+}
+
+// A server bound to a wildcard address is reached under whichever of the
+// machine's addresses the client used, so any IP literal with the right
+// port is accepted; a rebound DNS name still is not.
+fn host_allowed(host: &str, local_addr: SocketAddr) -> bool {
+    let localhost = format!("localhost:{}", local_addr.port());
+    let ip = local_addr.ip();
+    if ip.is_unspecified() {
+        return host.eq_ignore_ascii_case(&localhost)
+            || host
+                .parse::<SocketAddr>()
+                .is_ok_and(|addr| addr.port() == local_addr.port());
+    }
+    host == local_addr.to_string() || (ip.is_loopback() && host.eq_ignore_ascii_case(&localhost))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+fn text_response(status: StatusCode, text: &str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "text/plain")
+        .body(Full::new(Bytes::from(text.to_string())))
+        .expect("response is valid")
+}
+
+/// Accepts HTTP/1 connections on `addr` until the process exits.
+pub async fn serve(server: Arc<Server>, addr: SocketAddr) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to listen on {addr}"))?;
+    let local_addr = listener.local_addr()?;
+    eprintln!("op-sim listening on http://{local_addr}");
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let server = Arc::clone(&server);
+                async move { Ok::<_, Infallible>(server.respond(request, local_addr).await) }
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                eprintln!("connection error: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: &str = "127.0.0.1:8787";
+
+    fn post(content_type: &str, extra: &[(&str, &str)]) -> Request<Full<Bytes>> {
+        let mut request = Request::post("/")
+            .header("host", ADDR)
+            .header("content-type", content_type);
+        for (name, value) in extra {
+            request = request.header(*name, *value);
+        }
+        request
+            .body(Full::new(Bytes::from(
+                r#"{"jsonrpc":"2.0","id":1,"method":"version"}"#,
+            )))
+            .unwrap()
+    }
+
+    async fn status(request: Request<Full<Bytes>>) -> StatusCode {
+        let server = Arc::new(Server::new(SimulatorConfig::default()).unwrap());
+        server
+            .respond(request, ADDR.parse().unwrap())
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn accepts_json_from_a_local_client() {
+        assert_eq!(status(post("application/json", &[])).await, StatusCode::OK);
+        assert_eq!(
+            status(post("application/json; charset=utf-8", &[])).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_text_plain_posts() {
+        assert_eq!(
+            status(post("text/plain", &[])).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_with_an_origin() {
+        let request = post("application/json", &[("origin", "https://example.com")]);
+        assert_eq!(status(request).await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn checks_host_against_the_listening_address() {
+        let headers = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            headers.insert(HOST, host.parse().unwrap());
+            headers
+        };
+        let local: SocketAddr = ADDR.parse().unwrap();
+        assert!(check_headers(&headers("127.0.0.1:8787"), local).is_ok());
+        assert!(check_headers(&headers("localhost:8787"), local).is_ok());
+        assert!(check_headers(&headers("rebound.example.com:8787"), local).is_err());
+        assert!(check_headers(&headers("localhost:9000"), local).is_err());
+        let lan: SocketAddr = "192.168.1.5:8787".parse().unwrap();
+        assert!(check_headers(&headers("192.168.1.5:8787"), lan).is_ok());
+        assert!(check_headers(&headers("localhost:8787"), lan).is_err());
+    }
+
+    #[test]
+    fn accepts_any_local_address_when_bound_to_a_wildcard() {
+        let headers = |host: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+            headers.insert(HOST, host.parse().unwrap());
+            headers
+        };
+        for wildcard in ["0.0.0.0:8787", "[::]:8787"] {
+            let local: SocketAddr = wildcard.parse().unwrap();
+            for host in [
+                "127.0.0.1:8787",
+                "192.168.1.5:8787",
+                "[::1]:8787",
+                "localhost:8787",
+            ] {
+                assert!(check_headers(&headers(host), local).is_ok(), "{host}");
+            }
+            for host in ["rebound.example.com:8787", "127.0.0.1:9000", "127.0.0.1"] {
+                assert!(check_headers(&headers(host), local).is_err(), "{host}");
+            }
+        }
+    }
+}
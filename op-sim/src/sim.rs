@@ -0,0 +1,480 @@
+// sim
+// compiles a component once and runs it in fresh stores, one per input
+
+use crate::adapt;
+use crate::cache::{CacheStatus, ComponentCache};
+use crate::determinism::Determinism;
+use crate::envelope::Timings;
+use crate::faults::FaultRules;
+use crate::http::{HttpProvider, HttpSource};
+use crate::limits::{self, LimitExceeded, Limiter, Limits};
+use crate::log::{LogCollector, LogEntry};
+use crate::metrics::{millis, FetchMetrics, Metrics};
+use crate::network::NetworkPolicy;
+use crate::profile::{self, Profile, Profiler};
+use crate::secrets::SecretsProvider;
+use crate::trace::TraceEntry;
+use crate::variance::NumericVariance;
+use crate::worlds::{self, ProviderPre, WorldVersion};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime::component::{Component, Linker, ResourceTable};
+use wasmtime::{Config, Engine, Store, Trap, UpdateDeadline};
+use wasmtime_wasi::p2::pipe::MemoryOutputPipe;
+use wasmtime_wasi::p2::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
+use wasmtime_wasi_http::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{HostFutureIncomingResponse, OutgoingRequestConfig};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
+
+// fuel budget given to the guest (same as operator)
+pub const FUEL_BUDGET: u64 = 100_000_000;
+
+pub struct MyCtx {
+    table: ResourceTable,
+    wasi: WasiCtx,
+    pub(crate) http: HttpProvider,
+    pub(crate) secrets: SecretsProvider,
+    pub(crate) log: LogCollector,
+    profiler: Option<Profiler>,
+    limiter: Limiter,
+    network: Arc<NetworkPolicy>,
+    // built on the first wasi:http request of the run
+    wasi_http_client: Option<reqwest::Client>,
+    wasi_http_ctx: WasiHttpCtx,
+}
+
+impl IoView for MyCtx {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiView for MyCtx {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+impl WasiHttpView for MyCtx {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.wasi_http_ctx
+    }
+
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        let client = match &self.wasi_http_client {
+            Some(client) => client,
+            None => match self.network.wasi_http_client() {
+                Ok(client) => self.wasi_http_client.insert(client),
+                Err(e) => return Err(ErrorCode::InternalError(Some(e.to_string())).into()),
+            },
+        };
+        let uri = request.uri().to_string();
+        match self
+            .network
+            .send_wasi_http(client, self.http.run_started(), request, config)
+        {
+            Ok(response) => Ok(response),
+            Err(reason) => {
+                self.log.host(
+                    "warn",
+                    format!("wasi:http request to {uri} denied: {reason}"),
+                );
+                Err(ErrorCode::HttpRequestDenied.into())
+            }
+        }
+    }
+}
+
+/// Engine configured the way the operator runs guests.
+pub fn engine() -> anyhow::Result<Engine> {
+    let mut wasm_config = Config::new();
+    wasm_config.wasm_component_model(true);
+    wasm_config.async_support(true);
+
+    wasm_config.consume_fuel(true);
+    wasm_config.epoch_interruption(true);
+    wasm_config.max_wasm_stack(1024 * 1024 * 32); // 32 MiB
+    wasm_config.async_stack_size(1024 * 1024 * 64); // 64 MiB
+
+    Engine::new(&wasm_config)
+}
+
+/// Compiles a component, or a wasip1 core module after adapting it.
+pub fn compile(
+    engine: &Engine,
+    wasm_bytes: &[u8],
+    cache: Option<&ComponentCache>,
+) -> anyhow::Result<(Component, CacheStatus)> {
+    match cache {
+        Some(cache) => cache.load_or_compile(engine, wasm_bytes),
+        None => {
+            let component = Component::new(engine, adapt::componentize(wasm_bytes)?)?;
+            Ok((component, CacheStatus::Disabled))
+        }
+    }
+}
+
+/// Linker with every interface the operator provides to guests.
+pub fn linker(engine: &Engine) -> anyhow::Result<Linker<MyCtx>> {
+    let mut linker = Linker::new(engine);
+    linker.allow_shadowing(true);
+
+    // add wasi functions to linker
+    wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
+
+    // add wasi http to linker
+    wasmtime_wasi_http::add_to_linker_async(&mut linker)?;
+
+    // add the newton provider interfaces of every world version to linker
+    worlds::add_to_linker(&mut linker)?;
+
+    Ok(linker)
+}
+
+/// Host configuration shared by every run of a simulator.
+#[derive(Clone)]
+pub struct SimulatorConfig {
+    pub http: HttpSource,
+    pub secrets: SecretsProvider,
+    pub network: Arc<NetworkPolicy>,
+    /// where precompiled components are cached, `None` to always compile
+    pub cache: Option<ComponentCache>,
+    pub limits: Limits,
+    pub determinism: Determinism,
+    /// random perturbation of numbers in fetched JSON, `None` to serve responses as-is
+    pub variance: Option<NumericVariance>,
+    /// faults injected into guest fetches, `None` to inject none
+    pub faults: Option<Arc<FaultRules>>,
+    /// keep a redacted log of every guest fetch in the result
+    pub http_trace: bool,
+    /// print guest log entries to stderr as they are logged
+    pub stream_logs: bool,
+    /// sample guest stacks into a profile in the result
+    pub profile: bool,
+}
+
+impl Default for SimulatorConfig {
+    /// Live fetches under the default network policy, no secrets, no cache,
+    /// no limits beyond the fuel budget and the host's real clock and randomness.
+    fn default() -> Self {
+        Self {
+            http: HttpSource::Live,
+            secrets: SecretsProvider::new(None, false),
+            network: Arc::new(NetworkPolicy::default()),
+            cache: None,
+            limits: Limits::default(),
+            determinism: Determinism::default(),
+            variance: None,
+            faults: None,
+            http_trace: false,
+            stream_logs: false,
+            profile: false,
+        }
+    }
+}
+
+/// Where guest stdout and stderr go.
+pub enum GuestOutput {
+    /// guest stdout is forwarded to stderr, guest stderr is inherited
+    Stderr,
+    /// both are captured in memory
+    Capture {
+        stdout: MemoryOutputPipe,
+        stderr: MemoryOutputPipe,
+    },
+}
+
+impl GuestOutput {
+    // upper bound on captured guest stdout/stderr
+    const CAPACITY: usize = 1024 * 1024;
+
+    pub fn capture() -> Self {
+        GuestOutput::Capture {
+            stdout: MemoryOutputPipe::new(Self::CAPACITY),
+            stderr: MemoryOutputPipe::new(Self::CAPACITY),
+        }
+    }
+
+    pub fn contents(&self) -> (Vec<u8>, Vec<u8>) {
+        match self {
+            GuestOutput::Capture { stdout, stderr } => {
+                (stdout.contents().to_vec(), stderr.contents().to_vec())
+            }
+            GuestOutput::Stderr => Default::default(),
+        }
+    }
+}
+
+/// Outcome of a single run. The outer error covers everything op-sim itself
+/// fails on; the inner result is what the guest's `run` returned.
+pub struct SimulationResult {
+    pub outcome: anyhow::Result<Result<String, String>>,
+    pub metrics: Metrics,
+    pub timings: Timings,
+    /// the run's HTTP host, holding the recorded cassette in record mode
+    pub http: HttpProvider,
+    /// the run's secrets host, holding the ids that could not be resolved
+    pub secrets: SecretsProvider,
+    /// every guest fetch with credentials and secret values redacted, `None`
+    /// unless the configuration asked for a trace
+    pub http_trace: Option<Vec<TraceEntry>>,
+    /// entries the guest logged through newton:provider/log, and host notes
+    /// such as wasi:http requests the network policy denied
+    pub logs: Vec<LogEntry>,
+    /// entries logged past the collection limit
+    pub logs_dropped: usize,
+    /// sampled guest stacks, `None` unless the configuration asked for a profile
+    pub profile: Option<Profile>,
+    /// guest stdout and stderr, empty unless they were captured
+    pub guest_stdout: Vec<u8>,
+    pub guest_stderr: Vec<u8>,
+}
+
+/// Entry point for embedding op-sim: holds one engine and host configuration
+/// and compiles components against them.
+pub struct Simulator {
+    engine: Engine,
+    config: Arc<SimulatorConfig>,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> anyhow::Result<Self> {
+        let engine = engine()?;
+        if config.profile {
+            limits::start_epoch_ticker(&engine, profile::SAMPLE_INTERVAL);
+        } else if config.limits.deadline.is_some() {
+            limits::start_epoch_ticker(&engine, limits::EPOCH_TICK);
+        }
+        Ok(Self {
+            engine,
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn config(&self) -> &SimulatorConfig {
+        &self.config
+    }
+
+    /// Compiles a component once, to run it over any number of inputs. A
+    /// wasip1 core module is adapted to a component first.
+    pub fn load(&self, wasm_bytes: &[u8]) -> anyhow::Result<Simulation> {
+        let compile_started = Instant::now();
+        let (component, cache_status) =
+            compile(&self.engine, wasm_bytes, self.config.cache.as_ref())?;
+        let compile_time = compile_started.elapsed();
+
+        let linker = linker(&self.engine)?;
+        let (world, pre) = worlds::bind(&self.engine, &component, &linker)?;
+
+        Ok(Simulation {
+            engine: self.engine.clone(),
+            component,
+            world,
+            pre,
+            host: Arc::clone(&self.config),
+            compile_time,
+            cache_status,
+        })
+    }
+
+    /// Compiles a component and runs it once on `input`, capturing guest
+    /// stdout and stderr into the result.
+    pub async fn simulate(
+        &self,
+        wasm_bytes: &[u8],
+        input: &str,
+    ) -> anyhow::Result<SimulationResult> {
+        self.load(wasm_bytes)?
+            .run(input, &GuestOutput::capture())
+            .await
+    }
+}
+
+/// A compiled component, ready to run.
+pub struct Simulation {
+    engine: Engine,
+    component: Component,
+    world: &'static WorldVersion,
+    pre: ProviderPre,
+    host: Arc<SimulatorConfig>,
+    compile_time: Duration,
+    cache_status: CacheStatus,
+}
+
+impl Simulation {
+    /// The same compiled component with a different host configuration, such
+    /// as one per simulated operator. The engine is shared, so a deadline or
+    /// profile only takes effect if the simulator was created with one.
+    pub fn with_config(&self, config: SimulatorConfig) -> Simulation {
+        Simulation {
+            engine: self.engine.clone(),
+            component: self.component.clone(),
+            world: self.world,
+            pre: self.pre.clone(),
+            host: Arc::new(config),
+            compile_time: self.compile_time,
+            cache_status: self.cache_status,
+        }
+    }
+
+    /// The newton:provider world version the component targets.
+    pub fn world(&self) -> &'static WorldVersion {
+        self.world
+    }
+
+    pub async fn run(
+        &self,
+        input: &str,
+        guest_output: &GuestOutput,
+    ) -> anyhow::Result<SimulationResult> {
+        let started = Instant::now();
+        let mut timings = Timings::default();
+        timings.set_compile(self.compile_time);
+
+        // wasi context (exact same as operator)
+        let mut wasi = WasiCtxBuilder::new();
+        wasi.args(&["plugin", input]) // pass input as command line argument
+            .inherit_stdin();
+        match guest_output {
+            GuestOutput::Stderr => wasi.stdout(wasmtime_wasi::p2::stderr()).inherit_stderr(),
+            GuestOutput::Capture { stdout, stderr } => {
+                wasi.stdout(stdout.clone()).stderr(stderr.clone())
+            }
+        };
+        self.host.network.configure_wasi(&mut wasi); // allow network access (eg for HTTP requests) within the network policy
+        self.host.determinism.configure_wasi(&mut wasi);
+
+        let ctx = MyCtx {
+            table: ResourceTable::new(),
+            wasi_http_ctx: WasiHttpCtx::new(),
+            http: HttpProvider::new(self.host.http.mode(), Arc::clone(&self.host.network))?
+                .with_variance(self.host.variance)
+                .with_faults(self.host.faults.clone())
+                .with_trace(self.host.http_trace),
+            secrets: self.host.secrets.clone(),
+            log: LogCollector::new(self.host.stream_logs),
+            profiler: self.host.profile.then(|| Profiler::new(&self.component)),
+            limiter: Limiter::new(self.host.limits),
+            network: Arc::clone(&self.host.network),
+            wasi_http_client: None,
+            wasi: wasi.build(),
+        };
+
+        let mut store = Store::new(&self.engine, ctx);
+        store.limiter(|ctx| &mut ctx.limiter);
+
+        // Set fuel
+        store.set_fuel(FUEL_BUDGET)?;
+
+        // the epoch deadline stops guest code; the timeout also covers time
+        // spent waiting on the host, such as a slow fetch
+        let limits = self.host.limits;
+        if self.host.profile {
+            // sample on every tick; the deadline is then checked here too
+            let deadline = limits.deadline.map(|deadline| started + deadline);
+            store.set_epoch_deadline(1);
+            store.epoch_deadline_callback(move |mut store| {
+                if let Some(mut profiler) = store.data_mut().profiler.take() {
+                    profiler.sample(&store);
+                    store.data_mut().profiler = Some(profiler);
+                }
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(Trap::Interrupt.into());
+                }
+                Ok(UpdateDeadline::Continue(1))
+            });
+        } else {
+            store.set_epoch_deadline(limits.epoch_deadline());
+            store.epoch_deadline_trap();
+        }
+        let outcome = match limits.deadline {
+            Some(deadline) => tokio::time::timeout(
+                deadline,
+                run_guest(&self.pre, &mut store, input, &mut timings),
+            )
+            .await
+            .unwrap_or_else(|_| Err(LimitExceeded::Deadline { deadline }.into())),
+            None => run_guest(&self.pre, &mut store, input, &mut timings).await,
+        }
+        .map_err(|e| LimitExceeded::classify(e, FUEL_BUDGET, &limits));
+
+        let fuel_remaining = store.get_fuel()?;
+        let metrics = Metrics {
+            fuel_budget: FUEL_BUDGET,
+            fuel_consumed: FUEL_BUDGET - fuel_remaining,
+            fuel_remaining,
+            peak_memory_bytes: store.data().limiter.memory.peak_memory_bytes(),
+            fetch: FetchMetrics::from_latencies(&store.data().http.fetch_latencies),
+            compile_ms: millis(self.compile_time),
+            component_cache: self.cache_status,
+            instantiate_ms: timings.instantiate_ms.unwrap_or_default(),
+            run_ms: timings.run_ms.unwrap_or_default(),
+            wall_time_ms: millis(started.elapsed()),
+        };
+        timings.wall_time_ms = metrics.wall_time_ms;
+
+        let mut ctx = store.into_data();
+        let profile = ctx.profiler.take().map(Profiler::finish).transpose()?;
+        let http_trace = ctx
+            .http
+            .trace
+            .take()
+            .map(|trace| trace.finish(ctx.secrets.values()));
+        if let Some(faults) = &mut ctx.http.faults {
+            faults.redact_urls(ctx.secrets.values());
+        }
+        let outcome = outcome.and_then(|result| {
+            if !ctx.http.unmatched.is_empty() {
+                anyhow::bail!(
+                    "no recorded interaction or mock rule for the following request(s): {}",
+                    ctx.http.unmatched.join(", ")
+                );
+            }
+            if ctx.secrets.is_strict() && !ctx.secrets.missing().is_empty() {
+                anyhow::bail!(
+                    "the following secret(s) could not be resolved: {}",
+                    ctx.secrets.missing().join(", ")
+                );
+            }
+            Ok(result)
+        });
+
+        let (guest_stdout, guest_stderr) = guest_output.contents();
+        Ok(SimulationResult {
+            outcome,
+            metrics,
+            timings,
+            http: ctx.http,
+            secrets: ctx.secrets,
+            http_trace,
+            logs: ctx.log.entries,
+            logs_dropped: ctx.log.dropped,
+            profile,
+            guest_stdout,
+            guest_stderr,
+        })
+    }
+}
+
+async fn run_guest(
+    pre: &ProviderPre,
+    store: &mut Store<MyCtx>,
+    input: &str,
+    timings: &mut Timings,
+) -> anyhow::Result<Result<String, String>> {
+    // instantiate the newton provider world
+    let instantiate_started = Instant::now();
+    let newton_provider = pre.instantiate_async(&mut *store).await?;
+    timings.set_instantiate(instantiate_started.elapsed());
+
+    // execute the run function with JSON input
+    let run_started = Instant::now();
+    let result = newton_provider.call_run(&mut *store, input).await;
+    timings.set_run(run_started.elapsed());
+    result
+}
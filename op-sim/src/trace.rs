@@ -0,0 +1,353 @@
+// trace
+// logs every guest fetch with credentials and secret values redacted
+
+use crate::metrics::millis;
+use crate::newton::provider::http::{HttpRequest, HttpResponse};
+use anyhow::Context;
+use base64::Engine as _;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// bytes of each body kept in the trace
+const BODY_PREVIEW_BYTES: usize = 1024;
+// secret values shorter than this are not redacted from the trace, since
+// replacing every occurrence of them would mangle unrelated text
+const MIN_SECRET_LEN: usize = 4;
+const REDACTED: &str = "[REDACTED]";
+
+// headers whose value is always redacted, besides any whose name contains
+// one of SENSITIVE_NAME_PARTS
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+// query parameter and header names containing one of these (ignoring case
+// and `-`/`_`) are treated as credentials
+const SENSITIVE_NAME_PARTS: &[&str] = &["key", "token", "secret", "password", "signature", "auth"];
+
+/// One fetch as the guest saw it: the request after the network policy and
+/// the response or error it got back, faults included.
+struct Exchange {
+    started: Duration,
+    latency: Duration,
+    request: HttpRequest,
+    result: Result<HttpResponse, String>,
+}
+
+/// Every fetch of a run, kept unredacted until the run ends and all the
+/// secrets the guest looked up are known.
+pub struct HttpTrace {
+    run_started: Instant,
+    exchanges: Vec<Exchange>,
+}
+
+impl HttpTrace {
+    pub fn new(run_started: Instant) -> Self {
+        Self {
+            run_started,
+            exchanges: Vec::new(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        started: Instant,
+        request: HttpRequest,
+        result: &Result<HttpResponse, String>,
+    ) {
+        self.exchanges.push(Exchange {
+            started: started.duration_since(self.run_started),
+            latency: started.elapsed(),
+            request,
+            result: result.clone(),
+        });
+    }
+
+    /// Redacts credentials and the given secret values, and cuts bodies down
+    /// to a preview.
+    pub fn finish<'a>(self, secrets: impl IntoIterator<Item = &'a str>) -> Vec<TraceEntry> {
+        let redactor = Redactor::new(secrets);
+        self.exchanges
+            .into_iter()
+            .map(|exchange| redactor.entry(exchange))
+            .collect()
+    }
+}
+
+/// A traced fetch, one line of the trace file.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    /// milliseconds from the start of the run to the fetch
+    pub started_ms: f64,
+    pub latency_ms: f64,
+    pub method: String,
+    pub url: String,
+    pub request_headers: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<BodyPreview>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub response_headers: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_body: Option<BodyPreview>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The start of a body, as lossy UTF-8, and its full length.
+#[derive(Debug, Clone, Serialize)]
+pub struct BodyPreview {
+    pub bytes: usize,
+    pub preview: String,
+    pub truncated: bool,
+}
+
+/// Writes a trace as JSON Lines, one fetch per line.
+pub fn save(path: &Path, entries: &[TraceEntry]) -> anyhow::Result<()> {
+    let mut file = std::fs::File::create(path)
+        .with_context(|| format!("failed to create trace file {}", path.display()))?;
+    for entry in entries {
+        serde_json::to_writer(&mut file, entry)?;
+        writeln!(file)?;
+    }
+    Ok(())
+}
+
+pub(crate) struct Redactor {
+    // every form of every secret, longest first, so a secret containing
+    // another is redacted whole
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    pub(crate) fn new<'a>(secrets: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut forms: Vec<String> = secrets
+            .into_iter()
+            .filter(|s| s.len() >= MIN_SECRET_LEN)
+            .flat_map(encoded_forms)
+            .filter(|form| form.len() >= MIN_SECRET_LEN)
+            .collect();
+        forms.sort_by_key(|form| std::cmp::Reverse(form.len()));
+        forms.dedup();
+        Self { secrets: forms }
+    }
+
+    fn entry(&self, exchange: Exchange) -> TraceEntry {
+        let request = exchange.request;
+        let (status, response_headers, response_body, error) = match exchange.result {
+            Ok(response) => (
+                Some(response.status),
+                self.headers(response.headers),
+                Some(self.body(&response.body)),
+                None,
+            ),
+            Err(error) => (None, Vec::new(), None, Some(self.text(&error))),
+        };
+        TraceEntry {
+            started_ms: millis(exchange.started),
+            latency_ms: millis(exchange.latency),
+            method: request.method,
+            url: self.url(&request.url),
+            request_headers: self.headers(request.headers),
+            request_body: request.body.as_deref().map(|body| self.body(body)),
+            status,
+            response_headers,
+            response_body,
+            error,
+        }
+    }
+
+    fn text(&self, text: &str) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), REDACTED)
+        })
+    }
+
+    fn headers(&self, headers: Vec<(String, String)>) -> Vec<(String, String)> {
+        headers
+            .into_iter()
+            .map(|(name, value)| {
+                let lower = name.to_ascii_lowercase();
+                let value =
+                    if SENSITIVE_HEADERS.contains(&lower.as_str()) || is_sensitive_name(&lower) {
+                        REDACTED.to_string()
+                    } else {
+                        self.text(&value)
+                    };
+                (name, value)
+            })
+            .collect()
+    }
+
+    // redacts the values of credential-like query parameters, keeping the
+    // rest of the URL as the guest wrote it
+    pub(crate) fn url(&self, url: &str) -> String {
+        let url = self.text(url);
+        let Some((base, rest)) = url.split_once('?') else {
+            return url;
+        };
+        let (query, fragment) = match rest.split_once('#') {
+            Some((query, fragment)) => (query, Some(fragment)),
+            None => (rest, None),
+        };
+        let query = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if is_sensitive_name(name) => format!("{name}={REDACTED}"),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        match fragment {
+            Some(fragment) => format!("{base}?{query}#{fragment}"),
+            None => format!("{base}?{query}"),
+        }
+    }
+
+    // secrets are redacted from the whole body before it is cut, so that a
+    // secret straddling the cut is not half shown
+    fn body(&self, body: &[u8]) -> BodyPreview {
+        let text = self.text(&String::from_utf8_lossy(body));
+        let truncated = text.len() > BODY_PREVIEW_BYTES;
+        let mut end = BODY_PREVIEW_BYTES.min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        BodyPreview {
+            bytes: body.len(),
+            preview: text[..end].to_string(),
+            truncated,
+        }
+    }
+}
+
+// a secret as it appears literally, percent-encoded in a URL or form body,
+// and base64-encoded (standard or URL-safe), as in a Basic credential or a
+// JWT. Base64 text depends on where the secret starts within the encoded
+// data, so the characters that depend on the secret alone are taken for each
+// of the three alignments.
+fn encoded_forms(secret: &str) -> Vec<String> {
+    let mut forms = vec![
+        secret.to_string(),
+        percent_encode(secret, true, "%20"),
+        percent_encode(secret, false, "%20"),
+        percent_encode(secret, true, "+"),
+    ];
+    for offset in 0..3 {
+        let mut data = vec![0u8; offset];
+        data.extend_from_slice(secret.as_bytes());
+        let encoded = base64::engine::general_purpose::STANDARD_NO_PAD.encode(&data);
+        // characters holding bits of the leading padding, and a last one
+        // holding bits of whatever follows the secret
+        let start = [0, 2, 3][offset];
+        let end = data.len() * 8 / 6;
+        if let Some(core) = encoded.get(start..end) {
+            forms.push(core.to_string());
+            forms.push(core.replace('+', "-").replace('/', "_"));
+        }
+    }
+    forms
+}
+
+fn percent_encode(text: &str, upper_hex: bool, space: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            b' ' => space.to_string(),
+            _ if upper_hex => format!("%{byte:02X}"),
+            _ => format!("%{byte:02x}"),
+        })
+        .collect()
+}
+
+fn is_sensitive_name(name: &str) -> bool {
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    SENSITIVE_NAME_PARTS.iter().any(|part| name.contains(part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+
+    const SECRET: &str = "s3cr3t/k+y=42 ok";
+
+    #[test]
+    fn redacts_literal_and_percent_encoded_secrets() {
+        let redactor = Redactor::new([SECRET]);
+        for text in [
+            format!("token {SECRET} end"),
+            "q=s3cr3t%2Fk%2By%3D42%20ok&x=1".to_string(),
+            "q=s3cr3t%2fk%2by%3d42%20ok&x=1".to_string(),
+            "q=s3cr3t%2Fk%2By%3D42+ok&x=1".to_string(),
+        ] {
+            let redacted = redactor.text(&text);
+            assert!(redacted.contains(REDACTED), "{text} -> {redacted}");
+            assert!(!redacted.contains("s3cr3t"), "{text} -> {redacted}");
+        }
+    }
+
+    #[test]
+    fn redacts_base64_encoded_secrets_at_any_alignment() {
+        let redactor = Redactor::new([SECRET]);
+        for prefix in ["", "u", "us", "user:"] {
+            let data = format!("{prefix}{SECRET}");
+            for encoded in [STANDARD.encode(&data), URL_SAFE_NO_PAD.encode(&data)] {
+                let redacted = redactor.text(&format!("Basic {encoded}"));
+                assert!(
+                    redacted.contains(REDACTED),
+                    "{data}: {encoded} -> {redacted}"
+                );
+                assert!(
+                    redacted.len() < encoded.len(),
+                    "{data}: {encoded} -> {redacted}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_short_secrets_alone() {
+        let redactor = Redactor::new(["abc"]);
+        assert_eq!(redactor.text("abcdef"), "abcdef");
+    }
+
+    #[test]
+    fn redacts_credential_headers_and_query_parameters() {
+        let redactor = Redactor::new([]);
+        let headers = redactor.headers(vec![
+            ("Authorization".to_string(), "Bearer abc".to_string()),
+            ("X-Api-Key".to_string(), "abc".to_string()),
+            ("Accept".to_string(), "application/json".to_string()),
+        ]);
+        assert_eq!(headers[0].1, REDACTED);
+        assert_eq!(headers[1].1, REDACTED);
+        assert_eq!(headers[2].1, "application/json");
+        assert_eq!(
+            redactor.url("https://api.example.com/v1?api_key=abc&id=7#top"),
+            "https://api.example.com/v1?api_key=[REDACTED]&id=7#top"
+        );
+    }
+
+    #[test]
+    fn redacts_secrets_before_cutting_bodies() {
+        let redactor = Redactor::new([SECRET]);
+        let mut body = "x".repeat(BODY_PREVIEW_BYTES - 4).into_bytes();
+        body.extend_from_slice(SECRET.as_bytes());
+        let preview = redactor.body(&body);
+        assert!(preview.truncated);
+        assert!(!preview.preview.contains("s3cr"));
+        assert_eq!(preview.bytes, body.len());
+    }
+}
@@ -0,0 +1,72 @@
+// variance
+// perturbs numbers in JSON fetch responses, to simulate operators seeing slightly different data
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+
+/// Scales every number with a fractional part in a JSON response body by a
+/// random factor in `[1 - max_relative, 1 + max_relative]`. Integers (ids,
+/// timestamps, counts) and non-JSON bodies are left alone.
+#[derive(Debug, Clone, Copy)]
+pub struct NumericVariance {
+    max_relative: f64,
+    pub seed: u64,
+}
+
+impl NumericVariance {
+    /// Variance of up to `percent` percent either way, which must be finite
+    /// and not negative.
+    pub fn from_percent(percent: f64, seed: u64) -> Result<Self, String> {
+        if !percent.is_finite() || percent < 0.0 {
+            return Err(format!(
+                "numeric variance must be a non-negative number of percent, not {percent}"
+            ));
+        }
+        Ok(Self {
+            max_relative: percent / 100.0,
+            seed,
+        })
+    }
+
+    pub fn rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.seed)
+    }
+
+    pub fn apply(&self, rng: &mut StdRng, body: Vec<u8>) -> Vec<u8> {
+        let Ok(mut value) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+        self.perturb(rng, &mut value);
+        serde_json::to_vec(&value).unwrap_or(body)
+    }
+
+    fn perturb(&self, rng: &mut StdRng, value: &mut Value) {
+        match value {
+            Value::Number(number) if number.is_f64() => {
+                let factor = 1.0 + rng.gen_range(-self.max_relative..=self.max_relative);
+                if let Some(scaled) = number
+                    .as_f64()
+                    .and_then(|n| serde_json::Number::from_f64(n * factor))
+                {
+                    *number = scaled;
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.perturb(rng, value)),
+            Value::Object(fields) => fields
+                .values_mut()
+                .for_each(|value| self.perturb(rng, value)),
+            _ => {}
+        }
+    }
+}
+
+/// Parses a `--numeric-variance` percentage, rejecting negative and
+/// non-finite values.
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    let percent: f64 = value
+        .parse()
+        .map_err(|e| format!("expected a number of percent: {e}"))?;
+    NumericVariance::from_percent(percent, 0)?;
+    Ok(percent)
+}
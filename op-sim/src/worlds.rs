@@ -0,0 +1,352 @@
+// worlds
+// the newton:provider world versions op-sim carries bindings for, and which one a component targets
+
+use crate::http::HttpProvider;
+use crate::log::LogCollector;
+use crate::newton::provider as current;
+use crate::secrets::SecretsProvider;
+use crate::sim::MyCtx;
+use anyhow::bail;
+use serde::Serialize;
+use wasmtime::component::{Component, HasSelf, InstancePre, Linker};
+use wasmtime::{Engine, Store};
+
+/// Bindings for `newton:provider@0.2.0`, generated from `wit/0.2.0`.
+pub mod v0_2_0 {
+    wasmtime::component::bindgen!({
+        path: "wit/0.2.0",
+        async: true,
+    });
+}
+
+/// Bindings for `newton:provider@0.1.0`, generated from `wit/0.1.0`.
+pub mod v0_1_0 {
+    wasmtime::component::bindgen!({
+        path: "wit/0.1.0",
+        async: true,
+    });
+}
+
+pub const PROVIDER_PACKAGE: &str = "newton:provider";
+
+/// Whether operators still run components targeting a world version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatorSupport {
+    /// a version op-sim provides ahead of operators; op-sim runs it, with a warning
+    Preview,
+    /// the version operators currently provide
+    Current,
+    /// an older version operators still provide
+    Supported,
+    /// a version operators no longer provide; op-sim still runs it, with a warning
+    Retired,
+}
+
+/// A version of the newton:provider world.
+pub struct WorldVersion {
+    pub version: &'static str,
+    /// interfaces the world imports
+    pub interfaces: &'static [&'static str],
+    pub operator_support: OperatorSupport,
+    bind: fn(InstancePre<MyCtx>) -> anyhow::Result<ProviderPre>,
+}
+
+/// Every world version op-sim carries bindings for, newest first. Adding one
+/// takes its WIT under `wit/<version>`, a bindings module above, a variant of
+/// [`ProviderPre`] and [`Provider`], and its interfaces in [`add_to_linker`].
+pub const WORLDS: &[WorldVersion] = &[
+    WorldVersion {
+        version: "0.2.0",
+        interfaces: &["http", "secrets", "log"],
+        operator_support: OperatorSupport::Preview,
+        bind: |pre| Ok(ProviderPre::V0_2_0(v0_2_0::NewtonProviderPre::new(pre)?)),
+    },
+    WorldVersion {
+        version: "0.1.0",
+        interfaces: &["http", "secrets"],
+        operator_support: OperatorSupport::Current,
+        bind: |pre| Ok(ProviderPre::V0_1_0(v0_1_0::NewtonProviderPre::new(pre)?)),
+    },
+];
+
+impl WorldVersion {
+    pub fn find(version: &str) -> Option<&'static WorldVersion> {
+        WORLDS.iter().find(|world| world.version == version)
+    }
+
+    /// The version operators currently provide.
+    pub fn current() -> &'static WorldVersion {
+        WORLDS
+            .iter()
+            .find(|world| world.operator_support == OperatorSupport::Current)
+            .expect("one world version is current")
+    }
+
+    pub fn name(&self) -> String {
+        format!("{PROVIDER_PACKAGE}@{}", self.version)
+    }
+
+    /// The warning to show for a component targeting this version, if any.
+    pub fn support_warning(&self) -> Option<String> {
+        match self.operator_support {
+            OperatorSupport::Preview => Some(format!(
+                "the component targets {}, which op-sim provides ahead of operators; operators currently provide {}",
+                self.name(),
+                WorldVersion::current().name()
+            )),
+            OperatorSupport::Retired => Some(format!(
+                "the component targets {}, which operators no longer support; rebuild it against {}",
+                self.name(),
+                WorldVersion::current().name()
+            )),
+            OperatorSupport::Current | OperatorSupport::Supported => None,
+        }
+    }
+}
+
+/// Names of every world version op-sim carries, for messages.
+pub fn known_versions() -> String {
+    WORLDS
+        .iter()
+        .map(WorldVersion::name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Splits an import name such as `newton:provider/http@0.1.0` into its
+/// interface and version, if it belongs to the newton:provider package.
+pub fn provider_import(name: &str) -> Option<(&str, &str)> {
+    let rest = name.strip_prefix(PROVIDER_PACKAGE)?.strip_prefix('/')?;
+    Some(rest.split_once('@').unwrap_or((rest, "")))
+}
+
+/// Picks the world version a component targets from the versions of its
+/// newton:provider imports. A component importing none of them, such as one
+/// that makes no fetches, gets the current version.
+pub fn select<'a>(
+    imports: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<&'static WorldVersion> {
+    let mut versions: Vec<&str> = Vec::new();
+    for (_, version) in imports.into_iter().filter_map(provider_import) {
+        if !versions.contains(&version) {
+            versions.push(version);
+        }
+    }
+    match versions.as_slice() {
+        [] => Ok(WorldVersion::current()),
+        [version] => match WorldVersion::find(version) {
+            Some(world) => Ok(world),
+            None => bail!(
+                "the component targets {PROVIDER_PACKAGE}@{version}, but op-sim carries bindings for {}",
+                known_versions()
+            ),
+        },
+        _ => bail!(
+            "the component imports several {PROVIDER_PACKAGE} versions ({}); it must target one",
+            versions.join(", ")
+        ),
+    }
+}
+
+/// Picks the world version a compiled component targets and binds it.
+pub fn bind(
+    engine: &Engine,
+    component: &Component,
+    linker: &Linker<MyCtx>,
+) -> anyhow::Result<(&'static WorldVersion, ProviderPre)> {
+    let ty = component.component_type();
+    let world = select(ty.imports(engine).map(|(name, _)| name))?;
+    let pre = (world.bind)(linker.instantiate_pre(component)?)?;
+    Ok((world, pre))
+}
+
+/// Adds the newton:provider interfaces of every world version to a linker.
+pub fn add_to_linker(linker: &mut Linker<MyCtx>) -> anyhow::Result<()> {
+    v0_2_0::newton::provider::http::add_to_linker::<MyCtx, HasSelf<HttpProvider>>(linker, |ctx| {
+        &mut ctx.http
+    })?;
+    v0_2_0::newton::provider::secrets::add_to_linker::<MyCtx, HasSelf<SecretsProvider>>(
+        linker,
+        |ctx| &mut ctx.secrets,
+    )?;
+    v0_2_0::newton::provider::log::add_to_linker::<MyCtx, HasSelf<LogCollector>>(linker, |ctx| {
+        &mut ctx.log
+    })?;
+    v0_1_0::newton::provider::http::add_to_linker::<MyCtx, HasSelf<HttpProvider>>(linker, |ctx| {
+        &mut ctx.http
+    })?;
+    v0_1_0::newton::provider::secrets::add_to_linker::<MyCtx, HasSelf<SecretsProvider>>(
+        linker,
+        |ctx| &mut ctx.secrets,
+    )?;
+    Ok(())
+}
+
+/// A component linked against one world version, ready to instantiate.
+#[derive(Clone)]
+pub enum ProviderPre {
+    V0_2_0(v0_2_0::NewtonProviderPre<MyCtx>),
+    V0_1_0(v0_1_0::NewtonProviderPre<MyCtx>),
+}
+
+impl ProviderPre {
+    pub async fn instantiate_async(&self, store: &mut Store<MyCtx>) -> anyhow::Result<Provider> {
+        Ok(match self {
+            ProviderPre::V0_2_0(pre) => Provider::V0_2_0(pre.instantiate_async(store).await?),
+            ProviderPre::V0_1_0(pre) => Provider::V0_1_0(pre.instantiate_async(store).await?),
+        })
+    }
+}
+
+/// An instantiated component.
+pub enum Provider {
+    V0_2_0(v0_2_0::NewtonProvider),
+    V0_1_0(v0_1_0::NewtonProvider),
+}
+
+impl Provider {
+    pub async fn call_run(
+        &self,
+        store: &mut Store<MyCtx>,
+        input: &str,
+    ) -> anyhow::Result<Result<String, String>> {
+        match self {
+            Provider::V0_2_0(provider) => provider.call_run(store, input).await,
+            Provider::V0_1_0(provider) => provider.call_run(store, input).await,
+        }
+    }
+}
+
+// the hosts implement the newest world version; older ones convert to it
+
+impl v0_1_0::newton::provider::http::Host for HttpProvider {
+    async fn fetch(
+        &mut self,
+        request: v0_1_0::newton::provider::http::HttpRequest,
+    ) -> Result<v0_1_0::newton::provider::http::HttpResponse, String> {
+        let request = current::http::HttpRequest {
+            url: request.url,
+            method: request.method,
+            headers: request.headers,
+            body: request.body,
+        };
+        let response = current::http::Host::fetch(self, request).await?;
+        Ok(v0_1_0::newton::provider::http::HttpResponse {
+            status: response.status,
+            headers: response.headers,
+            body: response.body,
+        })
+    }
+}
+
+impl v0_1_0::newton::provider::secrets::Host for SecretsProvider {
+    async fn get(&mut self, secret_id: String) -> Result<String, String> {
+        current::secrets::Host::get(self, secret_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected(imports: &[&str]) -> anyhow::Result<&'static str> {
+        select(imports.iter().copied()).map(|world| world.version)
+    }
+
+    #[test]
+    fn splits_provider_imports() {
+        assert_eq!(
+            provider_import("newton:provider/http@0.1.0"),
+            Some(("http", "0.1.0"))
+        );
+        assert_eq!(provider_import("newton:provider/log"), Some(("log", "")));
+        assert_eq!(provider_import("wasi:http/types@0.2.3"), None);
+        assert_eq!(provider_import("newton:providers/http@0.1.0"), None);
+    }
+
+    #[test]
+    fn selects_the_version_the_component_imports() {
+        assert_eq!(
+            selected(&["wasi:cli/stdout@0.2.3", "newton:provider/http@0.1.0"]).unwrap(),
+            "0.1.0"
+        );
+        assert_eq!(
+            selected(&[
+                "newton:provider/http@0.2.0",
+                "newton:provider/secrets@0.2.0"
+            ])
+            .unwrap(),
+            "0.2.0"
+        );
+    }
+
+    #[test]
+    fn components_without_provider_imports_get_the_current_version() {
+        assert_eq!(
+            selected(&["wasi:cli/environment@0.2.3"]).unwrap(),
+            WorldVersion::current().version
+        );
+        assert_eq!(selected(&[]).unwrap(), WorldVersion::current().version);
+    }
+
+    #[test]
+    fn rejects_unknown_and_mixed_versions() {
+        let unknown = selected(&["newton:provider/http@9.9.9"]).unwrap_err();
+        assert!(unknown.to_string().contains("newton:provider@9.9.9"));
+        let mixed =
+            selected(&["newton:provider/http@0.1.0", "newton:provider/log@0.2.0"]).unwrap_err();
+        assert!(mixed.to_string().contains("0.1.0, 0.2.0"));
+    }
+
+    #[test]
+    fn retired_and_preview_versions_warn_instead_of_failing() {
+        let retired = WorldVersion {
+            version: "0.0.1",
+            interfaces: &["http"],
+            operator_support: OperatorSupport::Retired,
+            bind: WorldVersion::current().bind,
+        };
+        let warning = retired.support_warning().unwrap();
+        assert!(warning.contains("newton:provider@0.0.1, which operators no longer support"));
+        assert!(warning.contains(&WorldVersion::current().name()));
+
+        let supported = WorldVersion {
+            operator_support: OperatorSupport::Supported,
+            ..retired
+        };
+        assert!(supported.support_warning().is_none());
+        for world in WORLDS {
+            let warns = world.support_warning().is_some();
+            assert_eq!(warns, world.operator_support == OperatorSupport::Preview);
+        }
+    }
+
+    #[test]
+    fn operator_world_links_secrets() {
+        assert!(WorldVersion::current().interfaces.contains(&"secrets"));
+        let engine = crate::sim::engine().unwrap();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker).unwrap();
+        let component = Component::new(
+            &engine,
+            r#"(component
+                (import "newton:provider/secrets@0.1.0" (instance
+                    (export "get" (func (param "secret-id" string) (result (result string (error string)))))
+                ))
+            )"#,
+        )
+        .unwrap();
+        linker.instantiate_pre(&component).unwrap();
+    }
+
+    #[test]
+    fn exactly_one_version_is_current() {
+        let current = WORLDS
+            .iter()
+            .filter(|world| world.operator_support == OperatorSupport::Current)
+            .count();
+        assert_eq!(current, 1);
+        assert!(WorldVersion::current().support_warning().is_none());
+    }
+}
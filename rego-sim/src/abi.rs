@@ -0,0 +1,316 @@
+// abi
+// contract ABIs that intent calldata is decoded with, looked up by selector and optionally by `to` and chain
+
+use alloy_json_abi::{Function, JsonAbi};
+use alloy_primitives::Address;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// An ABI file, scoped to the intents sent to one contract, as given on the
+/// command line and in test cases: `[[CHAIN_ID:]ADDRESS=]PATH`, e.g.
+/// `NewtonErc20.abi.json` or `11155111:0x8f86403a4de0bb5791fa46b8e795c547942fe4cf=NewtonErc20.abi.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct AbiSpec {
+    pub chain_id: Option<u64>,
+    pub address: Option<Address>,
+    pub path: PathBuf,
+}
+
+impl FromStr for AbiSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let Some((key, path)) = spec.split_once('=') else {
+            return Ok(AbiSpec {
+                chain_id: None,
+                address: None,
+                path: PathBuf::from(spec),
+            });
+        };
+        let (chain_id, address) = match key.split_once(':') {
+            Some((chain_id, address)) => {
+                let chain_id = chain_id
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid chain id {:?} in {:?}: {}", chain_id, spec, e))?;
+                (Some(chain_id), address)
+            }
+            None => (None, key),
+        };
+        let address = address
+            .parse::<Address>()
+            .map_err(|e| format!("invalid address {:?} in {:?}: {}", address, spec, e))?;
+        Ok(AbiSpec {
+            chain_id,
+            address: Some(address),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl TryFrom<String> for AbiSpec {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        spec.parse()
+    }
+}
+
+impl AbiSpec {
+    /// The same spec with a relative path taken relative to `dir`.
+    pub fn relative_to(mut self, dir: &Path) -> Self {
+        self.path = dir.join(self.path);
+        self
+    }
+}
+
+struct ContractAbi {
+    chain_id: Option<u64>,
+    address: Option<Address>,
+    abi: JsonAbi,
+}
+
+/// Contract ABIs to decode intent calldata with when the intent has no
+/// `functionSignature`.
+#[derive(Default)]
+pub struct AbiRegistry {
+    contracts: Vec<ContractAbi>,
+}
+
+impl AbiRegistry {
+    /// Loads ABI files: a JSON array of ABI items, or a compiler artifact
+    /// (Foundry, Hardhat) holding one under `abi`.
+    pub fn load(specs: &[AbiSpec]) -> anyhow::Result<Self> {
+        let mut registry = AbiRegistry::default();
+        for spec in specs {
+            let contents = std::fs::read_to_string(&spec.path).map_err(|e| {
+                anyhow::anyhow!("failed to read ABI {}: {}", spec.path.display(), e)
+            })?;
+            let mut value: Value = serde_json::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("{} is not valid JSON: {}", spec.path.display(), e))?;
+            if let Some(abi) = value.get_mut("abi") {
+                value = abi.take();
+            }
+            let abi: JsonAbi = serde_json::from_value(value).map_err(|e| {
+                anyhow::anyhow!("{} is not a contract ABI: {}", spec.path.display(), e)
+            })?;
+            registry.contracts.push(ContractAbi {
+                chain_id: spec.chain_id,
+                address: spec.address,
+                abi,
+            });
+        }
+        Ok(registry)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contracts.is_empty()
+    }
+
+    /// The function `selector` calls on `to`. ABIs scoped to `to` on
+    /// `chain_id` are searched first, then those scoped to `to` on any
+    /// chain, then unscoped ones, each in the order they were given.
+    pub fn function(
+        &self,
+        to: Address,
+        chain_id: Option<u64>,
+        selector: [u8; 4],
+    ) -> Option<&Function> {
+        let mut candidates: Vec<(u8, &ContractAbi)> = self
+            .contracts
+            .iter()
+            .filter_map(|contract| {
+                let specificity = match (contract.address, contract.chain_id) {
+                    (None, _) => 0,
+                    (Some(address), _) if address != to => return None,
+                    (Some(_), None) => 1,
+                    (Some(_), Some(chain)) if Some(chain) == chain_id => 2,
+                    (Some(_), Some(_)) => return None,
+                };
+                Some((specificity, contract))
+            })
+            .collect();
+        // stable, so ABIs of the same specificity keep their order
+        candidates.sort_by_key(|(specificity, _)| std::cmp::Reverse(*specificity));
+        candidates
+            .into_iter()
+            .flat_map(|(_, contract)| contract.abi.functions())
+            .find(|function| function.selector() == selector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_intent_with_abis;
+    use serde_json::json;
+
+    const TOKEN: &str = "0x8f86403A4DE0BB5791fa46B8e795C547942fE4Cf";
+    const OTHER: &str = "0x4883282094755c01cd0d15dfe74753c9e189d194";
+    // buy(address,uint256,uint32)
+    const BUY_SELECTOR: [u8; 4] = [0x28, 0xdc, 0xa9, 0xf7];
+
+    fn contract(chain_id: Option<u64>, address: Option<&str>, signature: &str) -> ContractAbi {
+        ContractAbi {
+            chain_id,
+            address: address.map(|address| address.parse().unwrap()),
+            abi: JsonAbi::parse([signature]).unwrap(),
+        }
+    }
+
+    fn registry(contracts: Vec<ContractAbi>) -> AbiRegistry {
+        AbiRegistry { contracts }
+    }
+
+    #[test]
+    fn parses_specs() {
+        let spec: AbiSpec = "Token.abi.json".parse().unwrap();
+        assert_eq!((spec.chain_id, spec.address), (None, None));
+        assert_eq!(spec.path, PathBuf::from("Token.abi.json"));
+
+        let spec: AbiSpec = format!("11155111:{TOKEN}=abis/Token.json").parse().unwrap();
+        assert_eq!(spec.chain_id, Some(11155111));
+        assert_eq!(spec.address, Some(TOKEN.parse().unwrap()));
+        assert_eq!(
+            spec.relative_to(Path::new("tests")).path,
+            PathBuf::from("tests/abis/Token.json")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_specs() {
+        let bad_address = "0x1234=Token.json".parse::<AbiSpec>().unwrap_err();
+        assert!(
+            bad_address.starts_with("invalid address \"0x1234\""),
+            "{bad_address}"
+        );
+        let bad_chain = format!("sepolia:{TOKEN}=Token.json")
+            .parse::<AbiSpec>()
+            .unwrap_err();
+        assert!(
+            bad_chain.starts_with("invalid chain id \"sepolia\""),
+            "{bad_chain}"
+        );
+        assert!(serde_json::from_value::<AbiSpec>(json!("-1:0x12=x.json")).is_err());
+    }
+
+    #[test]
+    fn prefers_the_most_specific_abi() {
+        let to: Address = TOKEN.parse().unwrap();
+        let abis = registry(vec![
+            contract(
+                None,
+                None,
+                "function buy(address anyToken, uint256, uint32)",
+            ),
+            contract(
+                None,
+                Some(TOKEN),
+                "function buy(address anyChain, uint256, uint32)",
+            ),
+            contract(
+                Some(1),
+                Some(TOKEN),
+                "function buy(address mainnet, uint256, uint32)",
+            ),
+            contract(
+                Some(11155111),
+                Some(TOKEN),
+                "function buy(address sepolia, uint256, uint32)",
+            ),
+            contract(
+                None,
+                Some(OTHER),
+                "function buy(address other, uint256, uint32)",
+            ),
+        ]);
+        let first_input = |chain_id: Option<u64>, to: Address| {
+            abis.function(to, chain_id, BUY_SELECTOR)
+                .map(|function| function.inputs[0].name.clone())
+        };
+        assert_eq!(first_input(Some(11155111), to).as_deref(), Some("sepolia"));
+        assert_eq!(first_input(Some(1), to).as_deref(), Some("mainnet"));
+        assert_eq!(first_input(Some(10), to).as_deref(), Some("anyChain"));
+        assert_eq!(first_input(None, to).as_deref(), Some("anyChain"));
+        let elsewhere: Address = "0x0000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        assert_eq!(first_input(Some(1), elsewhere).as_deref(), Some("anyToken"));
+        assert_eq!(abis.function(to, Some(1), [0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn decodes_intents_with_named_arguments() {
+        let abis = registry(vec![contract(
+            Some(11155111),
+            Some(TOKEN),
+            "function buy(address token, uint256 amount, uint32)",
+        )]);
+        let intent = json!({
+            "from": "0xF2168be2F3aE8989696705b211c7b9d65E3416dA",
+            "to": TOKEN,
+            "value": "0x0",
+            "data": "0x28dca9f70000000000000000000000008f86403a4de0bb5791fa46b8e795c547942fe4cf000000000000000000000000000000000000000000000000000000174876e8000000000000000000000000000000000000000000000000000000000000000002",
+            "chainId": "11155111"
+        });
+        let parsed = parse_intent_with_abis(intent.clone(), &abis).unwrap();
+        assert_eq!(
+            parsed.decoded_function_signature.as_deref(),
+            Some("function buy(address token, uint256 amount, uint32)")
+        );
+        assert_eq!(
+            parsed.decoded_function_named_arguments,
+            Some(
+                json!({ "token": "0x8f86403a4de0bb5791fa46b8e795c547942fe4cf", "amount": "100000000000" })
+            )
+        );
+
+        // an ABI scoped to another chain does not apply
+        let mut other_chain = intent;
+        other_chain["chainId"] = json!("1");
+        let parsed = parse_intent_with_abis(other_chain, &abis).unwrap();
+        assert_eq!(parsed.decoded_function_arguments, None);
+    }
+
+    #[test]
+    fn loads_abi_arrays_and_compiler_artifacts() {
+        let dir = std::env::temp_dir().join(format!("rego-sim-abi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let items = json!([{
+            "type": "function",
+            "name": "buy",
+            "inputs": [
+                { "name": "token", "type": "address" },
+                { "name": "amount", "type": "uint256" },
+                { "name": "", "type": "uint32" }
+            ],
+            "outputs": [],
+            "stateMutability": "nonpayable"
+        }]);
+        std::fs::write(dir.join("array.json"), items.to_string()).unwrap();
+        std::fs::write(
+            dir.join("artifact.json"),
+            json!({ "abi": items }).to_string(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("invalid.json"), r#"{"abi": 1}"#).unwrap();
+
+        let specs: Vec<AbiSpec> = ["array.json", "artifact.json"]
+            .iter()
+            .map(|file| file.parse::<AbiSpec>().unwrap().relative_to(&dir))
+            .collect();
+        let abis = AbiRegistry::load(&specs).unwrap();
+        assert_eq!(abis.contracts.len(), 2);
+        assert!(abis.function(Address::ZERO, None, BUY_SELECTOR).is_some());
+
+        let invalid = "invalid.json".parse::<AbiSpec>().unwrap().relative_to(&dir);
+        let error = AbiRegistry::load(&[invalid]).err().unwrap();
+        assert!(
+            error.to_string().contains("is not a contract ABI"),
+            "{error}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
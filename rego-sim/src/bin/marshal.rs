@@ -1,10 +1,31 @@
-use rego_sim::generate_local_policy_input_data;
+use rego_sim::{generate_local_policy_input_data, AbiSpec};
 use std::env;
+use std::path::Path;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // --abi <[[CHAIN_ID:]ADDRESS=]FILE> may appear anywhere, any number of times
+    let mut args: Vec<String> = Vec::new();
+    let mut abis: Vec<AbiSpec> = Vec::new();
+    let mut argv = env::args();
+    while let Some(arg) = argv.next() {
+        if arg != "--abi" {
+            args.push(arg);
+            continue;
+        }
+        match argv.next().map(|spec| spec.parse::<AbiSpec>()) {
+            Some(Ok(spec)) => abis.push(spec),
+            Some(Err(e)) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            None => {
+                eprintln!("Error: --abi needs a value");
+                std::process::exit(1);
+            }
+        }
+    }
     if args.len() < 6 {
-        eprintln!("Usage: marshal <policy.rego> <policy_params_data.json> <test_intent.json> <entrypoint> <output.json>");
+        eprintln!("Usage: marshal <policy.rego> <policy_params_data.json> <test_intent.json> <entrypoint> <output.json> [params_schema.json] [--abi [[CHAIN_ID:]ADDRESS=]FILE]...");
         std::process::exit(1);
     }
     let policy_path = &args[1];
@@ -12,14 +33,28 @@ fn main() {
     let intent_path = &args[3];
     let entrypoint = &args[4];
     let output_path = &args[5];
+    // without an explicit schema, use the one that sits next to the policy, as in a policy-files directory
+    let default_schema = Path::new(policy_path).with_file_name("params_schema.json");
+    let schema_path = match args.get(6) {
+        Some(path) => Some(path.as_str()),
+        None if default_schema.exists() => default_schema.to_str(),
+        None => None,
+    };
 
-    match generate_local_policy_input_data(policy_path, params_path, intent_path, None, entrypoint) {
+    match generate_local_policy_input_data(
+        policy_path,
+        params_path,
+        intent_path,
+        schema_path,
+        &abis,
+        entrypoint,
+    ) {
         Ok(result) => {
             std::fs::write(output_path, serde_json::to_string_pretty(&result).unwrap()).unwrap();
             println!("Wrote marshaled and evaluated result to {}", output_path);
         }
         Err(e) => {
-            eprintln!("Error: {:?}", e);
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
     }
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How a policy decided, from the value of its entrypoint rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The entrypoint evaluated to `true`
+    Allow,
+    /// The entrypoint evaluated to `false`
+    Deny,
+    /// The entrypoint is undefined for this input, e.g. a rule without a default whose body failed
+    Undefined,
+    /// The entrypoint evaluated to something other than a boolean
+    NonBoolean,
+}
+
+/// Result of evaluating a Rego policy against an input and data document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    /// Fully qualified rule path, e.g. `data.example_policy.allow`
+    pub entrypoint: String,
+    pub verdict: Verdict,
+    /// Value of the entrypoint rule; absent when undefined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    /// Output of `print` calls made while evaluating
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prints: Vec<String>,
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        self.verdict == Verdict::Allow
+    }
+}
+
+/// Prefixes `data.` to an entrypoint such as `example_policy.allow`, as the
+/// Newton operator does.
+pub fn qualify_entrypoint(entrypoint: &str) -> String {
+    if entrypoint == "data" || entrypoint.starts_with("data.") {
+        entrypoint.to_string()
+    } else {
+        format!("data.{}", entrypoint)
+    }
+}
+
+/// A policy loaded with an input and data document, to evaluate any number
+/// of its rules with the embedded regorus engine.
+pub struct Evaluator {
+    engine: regorus::Engine,
+}
+
+impl Evaluator {
+    /// `policy` is the Rego source, `input` the parsed intent and `data` the
+    /// data document (`{"params": ..., "data": ...}`).
+    pub fn new(policy: &str, input: &Value, data: &Value) -> anyhow::Result<Self> {
+        let mut engine = regorus::Engine::new();
+        engine.set_gather_prints(true);
+        engine
+            .add_policy("policy.rego".to_string(), policy.to_string())
+            .map_err(|e| anyhow::anyhow!("failed to parse policy: {}", e))?;
+        engine
+            .add_data(regorus::Value::from(data.clone()))
+            .map_err(|e| anyhow::anyhow!("invalid data document: {}", e))?;
+        engine.set_input(regorus::Value::from(input.clone()));
+        Ok(Self { engine })
+    }
+
+    /// Evaluates the rule at `entrypoint`, e.g. `example_policy.allow`.
+    pub fn rule(&mut self, entrypoint: &str) -> anyhow::Result<Decision> {
+        let entrypoint = qualify_entrypoint(entrypoint);
+        let result = self
+            .engine
+            .eval_rule(entrypoint.clone())
+            .map_err(|e| anyhow::anyhow!("failed to evaluate {}: {}", entrypoint, e))?;
+        let prints = self.engine.take_prints().unwrap_or_default();
+
+        let (verdict, value) = match result {
+            regorus::Value::Undefined => (Verdict::Undefined, None),
+            regorus::Value::Bool(true) => (Verdict::Allow, Some(Value::Bool(true))),
+            regorus::Value::Bool(false) => (Verdict::Deny, Some(Value::Bool(false))),
+            other => (Verdict::NonBoolean, Some(serde_json::to_value(&other)?)),
+        };
+
+        Ok(Decision {
+            entrypoint,
+            verdict,
+            value,
+            prints,
+        })
+    }
+}
+
+/// Evaluates the rule at `entrypoint` of a Rego policy with the embedded
+/// regorus engine.
+///
+/// `policy` is the Rego source, `input` the parsed intent and `data` the
+/// data document (`{"params": ..., "data": ...}`).
+pub fn evaluate_policy(
+    policy: &str,
+    input: &Value,
+    data: &Value,
+    entrypoint: &str,
+) -> anyhow::Result<Decision> {
+    Evaluator::new(policy, input, data)?.rule(entrypoint)
+}
+
+/// Converts a JSON value the way the engine does, so that values written by
+/// hand compare equal to rule values (e.g. `1.0` and `1`).
+pub fn rego_value(value: &Value) -> anyhow::Result<Value> {
+    Ok(serde_json::to_value(regorus::Value::from(value.clone()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const POLICY: &str = r#"
+package example
+
+default allow := false
+
+allow if input.amount < data.params.max
+
+label := "over" if input.amount >= data.params.max
+
+flagged if input.flag
+"#;
+
+    fn rule(entrypoint: &str) -> Decision {
+        let data = json!({ "params": { "max": 10 }, "data": {} });
+        Evaluator::new(POLICY, &json!({ "amount": 12, "flag": false }), &data)
+            .unwrap()
+            .rule(entrypoint)
+            .unwrap()
+    }
+
+    #[test]
+    fn qualifies_entrypoints_once() {
+        assert_eq!(qualify_entrypoint("example.allow"), "data.example.allow");
+        assert_eq!(
+            qualify_entrypoint("data.example.allow"),
+            "data.example.allow"
+        );
+        assert_eq!(qualify_entrypoint("data"), "data");
+        assert_eq!(qualify_entrypoint("database.allow"), "data.database.allow");
+    }
+
+    #[test]
+    fn maps_rule_values_to_verdicts() {
+        let deny = rule("example.allow");
+        assert_eq!(deny.verdict, Verdict::Deny);
+        assert_eq!(deny.value, Some(json!(false)));
+        assert_eq!(deny.entrypoint, "data.example.allow");
+
+        let non_boolean = rule("example.label");
+        assert_eq!(non_boolean.verdict, Verdict::NonBoolean);
+        assert_eq!(non_boolean.value, Some(json!("over")));
+        assert!(!non_boolean.is_allowed());
+
+        let undefined = rule("example.flagged");
+        assert_eq!(undefined.verdict, Verdict::Undefined);
+        assert_eq!(undefined.value, None);
+    }
+
+    #[test]
+    fn allows_and_gathers_prints() {
+        let policy = "package example\n\nallow if {\n\tprint(\"checking\", input.amount)\n\tinput.amount < 10\n}\n";
+        let decision =
+            evaluate_policy(policy, &json!({ "amount": 3 }), &json!({}), "example.allow").unwrap();
+        assert!(decision.is_allowed());
+        assert_eq!(decision.prints.len(), 1);
+        assert!(
+            decision.prints[0].contains("checking 3"),
+            "{:?}",
+            decision.prints
+        );
+    }
+
+    #[test]
+    fn reports_policies_that_do_not_parse() {
+        let error = evaluate_policy(
+            "package example\n\nallow if {",
+            &json!({}),
+            &json!({}),
+            "example.allow",
+        )
+        .unwrap_err();
+        assert!(
+            error.to_string().starts_with("failed to parse policy"),
+            "{error}"
+        );
+    }
+}
@@ -1,11 +1,22 @@
 use alloy_dyn_abi::JsonAbiExt;
 use alloy_dyn_abi::DynSolValue;
-use alloy_json_abi::Function;
+use alloy_json_abi::{Function, Param};
 use alloy_primitives::{Address, Bytes, U256};
 use eyre::Result;
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 use std::fs;
+use std::path::Path;
+
+pub mod abi;
+pub mod eval;
+pub mod schema;
+pub mod simulate;
+pub mod suite;
+
+pub use abi::{AbiRegistry, AbiSpec};
+pub use eval::{evaluate_policy, Decision, Verdict};
+pub use schema::validate_params;
 
 // Custom serializer for U256 to serialize as JSON number if it fits in u64, otherwise as string
 fn serialize_u256_as_decimal<S>(value: &U256, serializer: S) -> Result<S::Ok, S::Error>
@@ -33,9 +44,17 @@ pub struct ParsedIntent {
 	pub function: Option<serde_json::Value>,
 	pub decoded_function_signature: Option<String>,
 	pub decoded_function_arguments: Option<Vec<serde_json::Value>>,
+	// arguments by parameter name, with named tuples as objects; absent when no parameter is named
+	pub decoded_function_named_arguments: Option<serde_json::Value>,
 }
 
 pub fn parse_intent(value: serde_json::Value) -> anyhow::Result<ParsedIntent> {
+    parse_intent_with_abis(value, &AbiRegistry::default())
+}
+
+/// Parses an intent, decoding its calldata with the `functionSignature` it
+/// carries or, without one, with the function of `abis` its selector calls.
+pub fn parse_intent_with_abis(value: serde_json::Value, abis: &AbiRegistry) -> anyhow::Result<ParsedIntent> {
     let from = value
         .get("from")
         .and_then(|v| v.as_str())
@@ -94,10 +113,24 @@ pub fn parse_intent(value: serde_json::Value) -> anyhow::Result<ParsedIntent> {
                 None
             }
         }
+    } else if let (Some(data), false) = (&data, abis.is_empty()) {
+        tracing::info!("decoding calldata with contract ABIs");
+
+        match decode_calldata_with_abis(data, to, chain_id, abis) {
+            Ok((func, decoded_args)) => Some((func, decoded_args)),
+            Err(e) => {
+                tracing::warn!("failed to decode calldata: {}", e);
+                None
+            }
+        }
     } else {
         None
     };
 
+    let named_function_arguments = decoded.as_ref().and_then(|(func, decoded_function_arguments)| {
+        serialize_named_arguments(&func.inputs, decoded_function_arguments)
+    });
+
     let serialized_function_arguments = decoded.clone().map(|(_, decoded_function_arguments)| {
         decoded_function_arguments
             .iter()
@@ -115,6 +148,7 @@ pub fn parse_intent(value: serde_json::Value) -> anyhow::Result<ParsedIntent> {
         function: decoded.clone().map(|(func, _)| serde_json::to_value(&func).unwrap()),
         decoded_function_signature: decoded.map(|(func, _)| func.full_signature()),
         decoded_function_arguments: serialized_function_arguments,
+        decoded_function_named_arguments: named_function_arguments,
     })
 }
 
@@ -151,6 +185,72 @@ pub fn decode_calldata(calldata: &Bytes, function_signature: &Bytes) -> anyhow::
     Ok((func, inputs))
 }
 
+/// Decodes calldata with the function its selector calls on `to`, looked up in contract ABIs.
+pub fn decode_calldata_with_abis(
+    calldata: &Bytes,
+    to: Address,
+    chain_id: Option<u64>,
+    abis: &AbiRegistry,
+) -> anyhow::Result<(Function, Vec<DynSolValue>)> {
+    // Validate calldata length
+    if calldata.len() < 4 {
+        anyhow::bail!("Calldata too short: expected at least 4 bytes for function selector");
+    }
+
+    let selector: [u8; 4] = calldata[..4]
+        .try_into()
+        .map_err(|e| anyhow::anyhow!("Failed to extract function selector: {}", e))?;
+
+    let func = abis
+        .function(to, chain_id, selector)
+        .ok_or_else(|| anyhow::anyhow!("No ABI for {} has a function with selector 0x{}", to, hex::encode(selector)))?
+        .clone();
+
+    let inputs: Vec<DynSolValue> = func
+        .abi_decode_input(&calldata[4..])
+        .map_err(|e| anyhow::anyhow!("Failed to decode calldata: {}", e))?;
+
+    Ok((func, inputs))
+}
+
+/// Serialize decoded arguments into an object keyed by parameter name, skipping unnamed parameters.
+/// Returns None when no parameter is named, e.g. for functions parsed from a bare signature.
+pub fn serialize_named_arguments(params: &[Param], values: &[DynSolValue]) -> Option<serde_json::Value> {
+    let named: serde_json::Map<String, serde_json::Value> = params
+        .iter()
+        .zip(values)
+        .filter(|(param, _)| !param.name.is_empty())
+        .map(|(param, value)| (param.name.clone(), serialize_named_sol_value(&param.components, value)))
+        .collect();
+    (!named.is_empty()).then_some(serde_json::Value::Object(named))
+}
+
+// Serialize like serialize_sol_value, but tuples whose components are all named become objects
+fn serialize_named_sol_value(components: &[Param], value: &DynSolValue) -> serde_json::Value {
+    match value {
+        DynSolValue::Tuple(fields)
+            if !components.is_empty() && components.iter().all(|component| !component.name.is_empty()) =>
+        {
+            serde_json::Value::Object(
+                components
+                    .iter()
+                    .zip(fields)
+                    .map(|(component, field)| {
+                        (component.name.clone(), serialize_named_sol_value(&component.components, field))
+                    })
+                    .collect(),
+            )
+        }
+
+        // Arrays of tuples carry the tuple's components
+        DynSolValue::Array(a) | DynSolValue::FixedArray(a) if !components.is_empty() => {
+            serde_json::Value::Array(a.iter().map(|v| serialize_named_sol_value(components, v)).collect())
+        }
+
+        _ => serialize_sol_value(value),
+    }
+}
+
 /// Serialize the sol value to a JSON value with proper type handling and padding removal
 /// NOTE: uint and int are serialized as strings to preserve precision for large numbers
 /// NOTE: addresses are serialized as lowercase strings
@@ -200,15 +300,32 @@ pub fn serialize_sol_value(value: &DynSolValue) -> serde_json::Value {
         DynSolValue::Tuple(t) => serde_json::Value::Array(t.iter().map(serialize_sol_value).collect()),
     }
 }
+/// Builds the data document a policy is evaluated against: the policy params
+/// under `params` and the data provider's output, if any, under `data`.
+pub fn data_document(params: Value, wasm_data: Option<Value>) -> Value {
+    let mut data_obj = serde_json::Map::new();
+    data_obj.insert("params".to_string(), params);
+    if let Some(wasm_data) = wasm_data {
+        data_obj.insert("data".to_string(), wasm_data);
+    }
+    Value::Object(data_obj)
+}
+
 pub fn generate_local_policy_input_data(
-	_policy_path: &str,
+	policy_path: &str,
 	params_path: &str,
 	intent_path: &str,
-	_schema_path: Option<&str>,
+	schema_path: Option<&str>, // e.g. "params_schema.json"
+	abis: &[AbiSpec], // contract ABIs to decode calldata with when the intent has no functionSignature
 	entrypoint: &str, // e.g. "example.allow"
 ) -> Result<Value, String> {
 	// Read files
+	let policy = fs::read_to_string(policy_path).map_err(|_| "Missing policy file".to_string())?;
 	let params: Value = serde_json::from_str(&fs::read_to_string(params_path).map_err(|_| "Missing params file".to_string())?).map_err(|_| "Invalid params JSON".to_string())?;
+	if let Some(schema_path) = schema_path {
+		let schema = schema::load_schema(Path::new(schema_path)).map_err(|e| format!("{:#}", e))?;
+		validate_params(&params, &schema).map_err(|e| format!("Invalid params: {}", e))?;
+	}
 	let intent: Value = serde_json::from_str(&fs::read_to_string(intent_path).map_err(|_| "Missing intent file".to_string())?).map_err(|_| "Invalid intent JSON".to_string())?;
 
 	// Prepare intermediary directory
@@ -217,34 +334,37 @@ pub fn generate_local_policy_input_data(
 
 	// Write data.json (policy params and wasm simulation data)
 	let data_json_path = format!("{}/data.json", intermediary_dir);
-	let mut data_obj = serde_json::Map::new();
-	data_obj.insert("params".to_string(), params.clone());
 	// Merge with wasm_data.json
 	let wasm_data_path = format!("{}/wasm_data.json", intermediary_dir);
-	if let Ok(wasm_data_str) = fs::read_to_string(&wasm_data_path) {
-		if let Ok(wasm_data_json) = serde_json::from_str::<Value>(&wasm_data_str) {
-			data_obj.insert("data".to_string(), wasm_data_json);
-		}
-	}
-	let data_json = Value::Object(data_obj);
+	let wasm_data = fs::read_to_string(&wasm_data_path)
+		.ok()
+		.and_then(|wasm_data_str| serde_json::from_str::<Value>(&wasm_data_str).ok());
+	let data_json = data_document(params, wasm_data);
 	fs::write(&data_json_path, serde_json::to_string_pretty(&data_json).unwrap()).map_err(|e| format!("Failed to write data.json: {}", e))?;
 
 
     // Parse the intent and write parsed intent to input.json
     let input_json_path = format!("{}/input.json", intermediary_dir);
-    let parsed_intent = match parse_intent(intent) {
+    let abis = AbiRegistry::load(abis).map_err(|e| format!("Failed to load ABIs: {}", e))?;
+    let parsed_intent = match parse_intent_with_abis(intent, &abis) {
         Ok(parsed) => parsed,
         Err(e) => return Err(format!("Failed to parse intent: {}", e)),
     };
     fs::write(&input_json_path, serde_json::to_string_pretty(&parsed_intent).unwrap())
         .map_err(|e| format!("Failed to write input.json: {}", e))?;
 
+    // Evaluate the policy against the input and data just written
+    let input = serde_json::to_value(&parsed_intent).unwrap();
+    let decision = evaluate_policy(&policy, &input, &data_json, entrypoint)
+        .map_err(|e| format!("Failed to evaluate policy: {}", e))?;
+
 	Ok(serde_json::json!({
 		"status": "ok",
 		"wrote": {
 			"data_json": data_json_path,
 			"input_json": input_json_path
 		},
-		"entrypoint": entrypoint
+		"entrypoint": decision.entrypoint,
+		"decision": decision
 	}))
 }
\ No newline at end of file
@@ -0,0 +1,407 @@
+// rego-sim
+// command line front end: simulates a policy's data provider and evaluates its Rego policy
+//
+// usage: rego-sim simulate --policy-files <dir> --params <params.json> --intent <intent.json> --entrypoint <rule>
+//        rego-sim simulate --policy <policy.rego> --wasm <policy.wasm> [--wasm-args <json>] --params <params.json>
+//                          --intent <intent.json> --entrypoint <rule> [--abi <abi.json>] [--mock <rules>] [--json]
+//        rego-sim test <suite_dir>... [--filter <name>] [--junit <report.xml>] [--json <report.json>]
+//        rego-sim validate-params --params <params.json> --params-schema <params_schema.json>
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use op_sim::cache::CacheArgs;
+use op_sim::cassette::Cassette;
+use op_sim::http::HttpSource;
+use op_sim::mock::MockRules;
+use op_sim::secrets::SecretsProvider;
+use op_sim::{Simulator, SimulatorConfig};
+use rego_sim::schema;
+use rego_sim::simulate::{self, PolicyCase, Report};
+use rego_sim::suite::{Suite, TestReport};
+use rego_sim::{AbiRegistry, AbiSpec, Verdict};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Exit code when the policy allows the intent.
+const ALLOW_EXIT: i32 = 0;
+/// Exit code when the policy denies the intent, including an undefined or
+/// non-boolean entrypoint, which operators do not treat as an allow.
+const DENY_EXIT: i32 = 1;
+/// Exit code when the policy could not be evaluated, or a test suite could
+/// not be loaded.
+const ERROR_EXIT: i32 = 2;
+/// Exit code when every test case passed.
+const PASS_EXIT: i32 = 0;
+/// Exit code when a test case failed or could not be evaluated.
+const FAIL_EXIT: i32 = 1;
+
+#[derive(Parser)]
+#[command(name = "rego-sim")]
+#[command(about = "Simulate Newton policy evaluation")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a policy's data provider WASM, then evaluate its Rego policy against an intent
+    Simulate(Box<SimulateArgs>),
+    /// Run directories of policy test cases and check the rule values they expect
+    Test(TestArgs),
+    /// Check a policy params file against the policy's params schema
+    ValidateParams(ValidateParamsArgs),
+}
+
+#[derive(Args)]
+struct SimulateArgs {
+    /// Directory holding policy.rego, policy.wasm, wasm_args.json and params_schema.json, such as a policy example's policy-files
+    #[arg(long, value_name = "DIR")]
+    policy_files: Option<PathBuf>,
+    /// Rego policy file, instead of policy.rego in --policy-files
+    #[arg(long, value_name = "FILE", required_unless_present = "policy_files")]
+    policy: Option<PathBuf>,
+    /// Data provider WASM file, instead of policy.wasm in --policy-files
+    #[arg(long, value_name = "FILE", required_unless_present = "policy_files")]
+    wasm: Option<PathBuf>,
+    /// JSON input for the data provider, instead of wasm_args.json in --policy-files (default: {})
+    #[arg(long, value_name = "JSON", conflicts_with = "wasm_args_file")]
+    wasm_args: Option<String>,
+    /// File holding the JSON input for the data provider
+    #[arg(long, value_name = "FILE")]
+    wasm_args_file: Option<PathBuf>,
+    /// Policy params JSON file, seen by the policy as data.params
+    #[arg(long, value_name = "FILE")]
+    params: PathBuf,
+    /// JSON Schema the params must match, instead of params_schema.json in --policy-files
+    #[arg(long, value_name = "FILE")]
+    params_schema: Option<PathBuf>,
+    /// Intent JSON file, parsed and seen by the policy as input
+    #[arg(long, value_name = "FILE")]
+    intent: PathBuf,
+    /// Contract ABI file to decode intent calldata with when the intent has no functionSignature, optionally only for one address (and chain); repeatable
+    #[arg(long = "abi", value_name = "[[CHAIN_ID:]ADDRESS=]FILE")]
+    abis: Vec<AbiSpec>,
+    /// Rule to evaluate, e.g. example_policy.allow; data. is prefixed when missing
+    #[arg(long, value_name = "RULE")]
+    entrypoint: String,
+    /// Also write the data provider output, data.json and input.json to this directory
+    #[arg(long, value_name = "DIR")]
+    intermediary: Option<PathBuf>,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+    /// Serve the data provider's HTTP fetches from this op-sim cassette file instead of the network
+    #[arg(long, value_name = "CASSETTE", conflicts_with = "mock")]
+    replay: Option<PathBuf>,
+    /// Serve the data provider's HTTP fetches from a TOML or JSON file of op-sim mock rules
+    #[arg(long, value_name = "RULES")]
+    mock: Option<PathBuf>,
+    /// TOML or JSON file of secret id to value, served to the data provider
+    #[arg(long, value_name = "FILE")]
+    secrets_file: Option<PathBuf>,
+    /// Look up secrets missing from the file in environment variables named <PREFIX><SECRET_ID>
+    #[arg(long, value_name = "PREFIX")]
+    secrets_env_prefix: Option<String>,
+    #[command(flatten)]
+    cache: CacheArgs,
+}
+
+#[derive(Args)]
+struct TestArgs {
+    /// Suite directories, each holding *.test.json cases and an optional suite.json of defaults
+    #[arg(required = true)]
+    suites: Vec<PathBuf>,
+    /// Only run cases whose name contains this
+    #[arg(long, value_name = "NAME")]
+    filter: Option<String>,
+    /// Write a JUnit XML report to this file
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+    /// Write a JSON report to this file
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ValidateParamsArgs {
+    /// Policy params JSON file
+    #[arg(long, value_name = "FILE")]
+    params: PathBuf,
+    /// JSON Schema the params must match, such as a policy's params_schema.json
+    #[arg(long, value_name = "FILE")]
+    params_schema: PathBuf,
+}
+
+impl SimulateArgs {
+    /// An explicit path, or `name` in the policy files directory.
+    fn policy_file(&self, explicit: &Option<PathBuf>, name: &str) -> Option<PathBuf> {
+        explicit
+            .clone()
+            .or_else(|| self.policy_files.as_ref().map(|dir| dir.join(name)))
+    }
+
+    fn case(&self) -> anyhow::Result<PolicyCase> {
+        let policy_path = self
+            .policy_file(&self.policy, "policy.rego")
+            .expect("required by clap");
+        let wasm_path = self
+            .policy_file(&self.wasm, "policy.wasm")
+            .expect("required by clap");
+
+        let wasm_args = match (&self.wasm_args, &self.wasm_args_file) {
+            (Some(json), _) => json.clone(),
+            (None, Some(path)) => read(path)?,
+            (None, None) => match &self.policy_files {
+                Some(dir) if dir.join("wasm_args.json").exists() => {
+                    read(&dir.join("wasm_args.json"))?
+                }
+                _ => "{}".to_string(),
+            },
+        };
+
+        // a policy files directory without a params schema is checked against none
+        let params_schema = match self.policy_file(&self.params_schema, "params_schema.json") {
+            Some(path) if self.params_schema.is_some() || path.exists() => {
+                Some(schema::load_schema(&path)?)
+            }
+            _ => None,
+        };
+
+        Ok(PolicyCase {
+            policy: read(&policy_path)?,
+            wasm: std::fs::read(&wasm_path)
+                .with_context(|| format!("failed to read {}", wasm_path.display()))?,
+            wasm_args,
+            params: read_json(&self.params)?,
+            params_schema,
+            intent: read_json(&self.intent)?,
+            abis: AbiRegistry::load(&self.abis)?,
+            entrypoint: self.entrypoint.clone(),
+        })
+    }
+
+    fn config(&self) -> anyhow::Result<SimulatorConfig> {
+        let http = if let Some(path) = &self.replay {
+            HttpSource::Replay(Cassette::load(path)?)
+        } else if let Some(path) = &self.mock {
+            HttpSource::Mock(Arc::new(MockRules::load(path)?))
+        } else {
+            HttpSource::Live
+        };
+
+        let mut secrets = SecretsProvider::new(self.secrets_env_prefix.clone(), false);
+        if let Some(path) = &self.secrets_file {
+            secrets.load_file(path)?;
+        }
+
+        Ok(SimulatorConfig {
+            http,
+            secrets,
+            cache: self.cache.cache(),
+            ..SimulatorConfig::default()
+        })
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn read_json(path: &Path) -> anyhow::Result<Value> {
+    serde_json::from_str(&read(path)?)
+        .with_context(|| format!("{} is not valid JSON", path.display()))
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Simulate(args) => simulate(*args).await,
+        Command::Test(args) => test(args),
+        Command::ValidateParams(args) => validate_params(args),
+    };
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(ERROR_EXIT);
+        }
+    }
+}
+
+async fn simulate(args: SimulateArgs) -> anyhow::Result<i32> {
+    let case = args.case()?;
+    let simulator = Simulator::new(args.config()?)?;
+    let report = simulate::simulate(&simulator, &case).await?;
+
+    for warning in &report.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if let Some(dir) = &args.intermediary {
+        write_intermediary(dir, &report)?;
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(verdict_exit(report.decision.verdict))
+}
+
+fn verdict_exit(verdict: Verdict) -> i32 {
+    match verdict {
+        Verdict::Allow => ALLOW_EXIT,
+        Verdict::Deny | Verdict::Undefined | Verdict::NonBoolean => DENY_EXIT,
+    }
+}
+
+fn test(args: TestArgs) -> anyhow::Result<i32> {
+    let suites = args
+        .suites
+        .iter()
+        .map(|dir| Suite::load(dir))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let report = TestReport::new(
+        suites
+            .iter()
+            .map(|suite| suite.run(args.filter.as_deref()))
+            .collect(),
+    );
+
+    for suite in &report.suites {
+        suite.print_human();
+    }
+    println!(
+        "{} passed, {} failed, {} error(s)",
+        report.passed, report.failed, report.errors
+    );
+    if let Some(path) = &args.junit {
+        std::fs::write(path, report.junit())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    if let Some(path) = &args.json {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    Ok(if report.is_success() {
+        PASS_EXIT
+    } else {
+        FAIL_EXIT
+    })
+}
+
+fn validate_params(args: ValidateParamsArgs) -> anyhow::Result<i32> {
+    let schema = schema::load_schema(&args.params_schema)?;
+    schema::validate_params(&read_json(&args.params)?, &schema)?;
+    println!(
+        "{} matches {}",
+        args.params.display(),
+        args.params_schema.display()
+    );
+    Ok(PASS_EXIT)
+}
+
+// the files run_rego_policy.sh used to leave behind, for inspecting a decision
+fn write_intermediary(dir: &Path, report: &Report) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let files = [
+        ("wasm_data.json", &report.data["data"]),
+        ("data.json", &report.data),
+        ("input.json", &report.input),
+    ];
+    for (name, value) in files {
+        let path = dir.join(name);
+        std::fs::write(&path, serde_json::to_string_pretty(value)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn print_report(report: &Report) {
+    let decision = &report.decision;
+    println!("entrypoint:  {}", decision.entrypoint);
+    println!("world:       {}", report.world);
+    println!("wasm:        {:.1} ms", report.wasm_ms);
+    println!("data:        {}", report.data["data"]);
+    let verdict = match decision.verdict {
+        Verdict::Allow => "ALLOW",
+        Verdict::Deny => "DENY",
+        Verdict::Undefined => "DENY (entrypoint is undefined)",
+        Verdict::NonBoolean => "DENY (entrypoint is not a boolean)",
+    };
+    println!("verdict:     {}", verdict);
+    if decision.verdict == Verdict::NonBoolean {
+        if let Some(value) = &decision.value {
+            println!("value:       {}", value);
+        }
+    }
+    for line in &decision.prints {
+        println!("print:       {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_an_allow_exits_zero() {
+        assert_eq!(verdict_exit(Verdict::Allow), 0);
+        for verdict in [Verdict::Deny, Verdict::Undefined, Verdict::NonBoolean] {
+            assert_eq!(verdict_exit(verdict), 1, "{verdict:?}");
+        }
+        assert_ne!(ERROR_EXIT, ALLOW_EXIT);
+        assert_ne!(ERROR_EXIT, DENY_EXIT);
+    }
+
+    #[test]
+    fn policy_files_fill_in_missing_paths() {
+        let cli = Cli::try_parse_from([
+            "rego-sim",
+            "simulate",
+            "--policy-files",
+            "policy-files",
+            "--wasm",
+            "other.wasm",
+            "--params",
+            "params.json",
+            "--intent",
+            "intent.json",
+            "--entrypoint",
+            "example.allow",
+        ])
+        .unwrap();
+        let Command::Simulate(args) = cli.command else {
+            panic!("expected simulate");
+        };
+        assert_eq!(
+            args.policy_file(&args.policy, "policy.rego"),
+            Some(PathBuf::from("policy-files/policy.rego"))
+        );
+        assert_eq!(
+            args.policy_file(&args.wasm, "policy.wasm"),
+            Some(PathBuf::from("other.wasm"))
+        );
+    }
+
+    #[test]
+    fn simulate_needs_a_policy_without_policy_files() {
+        let parsed = Cli::try_parse_from([
+            "rego-sim",
+            "simulate",
+            "--wasm",
+            "policy.wasm",
+            "--params",
+            "params.json",
+            "--intent",
+            "intent.json",
+            "--entrypoint",
+            "example.allow",
+        ]);
+        assert!(parsed.is_err());
+    }
+}
@@ -0,0 +1,127 @@
+// schema
+// validates policy params against the policy's params_schema.json
+
+use anyhow::{bail, Context};
+use serde_json::Value;
+use std::path::Path;
+
+/// Reads a params schema, such as a policy's `params_schema.json`.
+pub fn load_schema(path: &Path) -> anyhow::Result<Value> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("{} is not valid JSON", path.display()))
+}
+
+/// Checks policy params against a JSON Schema, failing with one line per
+/// violation, each naming the field it is about, e.g.
+/// `params.min_30day_apy: 0.05 is not of type "string"`. Remote `$ref`s are
+/// not resolved; params schemas are expected to be self-contained.
+pub fn validate_params(params: &Value, schema: &Value) -> anyhow::Result<()> {
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| anyhow::anyhow!("invalid params schema: {}", e))?;
+    let violations: Vec<String> = validator
+        .iter_errors(params)
+        .map(|error| format!("{}: {}", field_path(error.instance_path().as_str()), error))
+        .collect();
+    if !violations.is_empty() {
+        bail!(
+            "params do not match the params schema:\n  {}",
+            violations.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+// turns a JSON pointer into the path the policy reads the field at, e.g.
+// /allowed_actions/11155111/max_limit into params.allowed_actions.11155111.max_limit
+fn field_path(pointer: &str) -> String {
+    let mut path = String::from("params");
+    for segment in pointer.split('/').skip(1) {
+        path.push('.');
+        path.push_str(&segment.replace("~1", "/").replace("~0", "~"));
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "min_30day_apy": { "type": "string" },
+                "allowed_actions": {
+                    "type": "object",
+                    "additionalProperties": {
+                        "type": "object",
+                        "properties": { "max_limit": { "type": "integer" } },
+                        "required": ["max_limit"]
+                    }
+                }
+            },
+            "required": ["min_30day_apy"]
+        })
+    }
+
+    fn violations(params: Value) -> Vec<String> {
+        let error = validate_params(&params, &schema()).unwrap_err().to_string();
+        let mut lines = error.lines();
+        assert_eq!(lines.next(), Some("params do not match the params schema:"));
+        lines.map(|line| line.trim().to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_matching_params() {
+        let params =
+            json!({ "min_30day_apy": "0.05", "allowed_actions": { "1": { "max_limit": 5 } } });
+        validate_params(&params, &schema()).unwrap();
+    }
+
+    #[test]
+    fn names_each_violation_by_the_field_the_policy_reads() {
+        let violations = violations(json!({
+            "min_30day_apy": 0.05,
+            "allowed_actions": { "11155111": { "max_limit": "lots" }, "1": {} }
+        }));
+        assert_eq!(violations.len(), 3, "{violations:?}");
+        assert!(violations
+            .contains(&r#"params.min_30day_apy: 0.05 is not of type "string""#.to_string()));
+        assert!(violations
+            .iter()
+            .any(|v| v.starts_with("params.allowed_actions.11155111.max_limit: ")));
+        assert!(violations
+            .iter()
+            .any(|v| v.starts_with("params.allowed_actions.1: ")));
+    }
+
+    #[test]
+    fn reports_missing_fields_at_the_top_level() {
+        assert_eq!(
+            violations(json!({})),
+            [r#"params: "min_30day_apy" is a required property"#]
+        );
+    }
+
+    #[test]
+    fn unescapes_json_pointer_segments() {
+        assert_eq!(field_path(""), "params");
+        assert_eq!(field_path("/a~1b/c~0d"), "params.a/b.c~d");
+    }
+
+    #[test]
+    fn rejects_invalid_schemas() {
+        let error = validate_params(&json!({}), &json!({ "type": "no-such-type" })).unwrap_err();
+        assert!(
+            error.to_string().starts_with("invalid params schema"),
+            "{error}"
+        );
+        let missing = load_schema(Path::new("/nonexistent/params_schema.json")).unwrap_err();
+        assert!(
+            missing.to_string().starts_with("failed to read"),
+            "{missing}"
+        );
+    }
+}
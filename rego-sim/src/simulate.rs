@@ -0,0 +1,144 @@
+// simulate
+// runs a policy end to end: its data provider WASM on op-sim, then its Rego policy over the output
+
+use crate::{
+    data_document, evaluate_policy, parse_intent_with_abis, validate_params, AbiRegistry, Decision,
+};
+use anyhow::{bail, Context};
+use op_sim::metrics::millis;
+use op_sim::sim::GuestOutput;
+use op_sim::Simulator;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Instant;
+
+/// One policy evaluation: the policy, its data provider and the intent to decide on.
+pub struct PolicyCase {
+    /// Rego source
+    pub policy: String,
+    /// data provider component, or a wasip1 module op-sim adapts
+    pub wasm: Vec<u8>,
+    /// JSON input passed to the data provider's `run`
+    pub wasm_args: String,
+    pub params: Value,
+    /// JSON Schema the params must match, such as the policy's `params_schema.json`
+    pub params_schema: Option<Value>,
+    /// the intent as submitted, before parsing
+    pub intent: Value,
+    /// contract ABIs to decode the intent's calldata with when it has no `functionSignature`
+    pub abis: AbiRegistry,
+    /// rule to evaluate, e.g. `example_policy.allow`
+    pub entrypoint: String,
+}
+
+/// What a simulation ran and how the policy decided.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub decision: Decision,
+    /// newton:provider world version the data provider targets
+    pub world: String,
+    /// milliseconds the data provider took, compilation included
+    pub wasm_ms: f64,
+    /// the parsed intent, as the policy sees it in `input`
+    pub input: Value,
+    /// params and data provider output, as the policy sees them in `data`
+    pub data: Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Checks the params against the params schema, if any, runs the data
+/// provider on `case.wasm_args`, builds the policy's input and data
+/// documents and evaluates the entrypoint. Guest stdout and stderr go to
+/// stderr. A data provider that fails or returns something other than
+/// JSON is an error, as it is for operators.
+pub async fn simulate(simulator: &Simulator, case: &PolicyCase) -> anyhow::Result<Report> {
+    if let Some(schema) = &case.params_schema {
+        validate_params(&case.params, schema)?;
+    }
+    let mut warnings = Vec::new();
+
+    let started = Instant::now();
+    let simulation = simulator
+        .load(&case.wasm)
+        .context("failed to load the data provider")?;
+    warnings.extend(simulation.world().support_warning());
+    let run = simulation
+        .run(&case.wasm_args, &GuestOutput::Stderr)
+        .await?;
+    let output = match run.outcome.context("data provider failed")? {
+        Ok(output) => output,
+        Err(error) => bail!("data provider returned an error: {}", error),
+    };
+    let wasm_ms = millis(started.elapsed());
+    if !run.secrets.missing().is_empty() {
+        warnings.push(format!(
+            "the following secret(s) could not be resolved: {}",
+            run.secrets.missing().join(", ")
+        ));
+    }
+    let wasm_data: Value =
+        serde_json::from_str(&output).context("data provider output is not JSON")?;
+
+    let input = serde_json::to_value(
+        parse_intent_with_abis(case.intent.clone(), &case.abis)
+            .context("failed to parse intent")?,
+    )?;
+    let data = data_document(case.params.clone(), Some(wasm_data));
+    let decision = evaluate_policy(&case.policy, &input, &data, &case.entrypoint)?;
+
+    Ok(Report {
+        decision,
+        world: simulation.world().name(),
+        wasm_ms,
+        input,
+        data,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use op_sim::SimulatorConfig;
+    use serde_json::json;
+
+    fn case(params: Value, wasm: &[u8]) -> PolicyCase {
+        PolicyCase {
+            policy: "package example\n\ndefault allow := false\n".to_string(),
+            wasm: wasm.to_vec(),
+            wasm_args: "{}".to_string(),
+            params,
+            params_schema: Some(json!({
+                "type": "object",
+                "properties": { "max": { "type": "integer" } },
+                "required": ["max"]
+            })),
+            intent: json!({}),
+            abis: AbiRegistry::default(),
+            entrypoint: "example.allow".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_params_before_running_the_data_provider() {
+        let simulator = Simulator::new(SimulatorConfig::default()).unwrap();
+        // the WASM is not even looked at when the params are invalid
+        let error = simulate(&simulator, &case(json!({ "max": "ten" }), b"not wasm"))
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("params.max"), "{error:#}");
+    }
+
+    #[tokio::test]
+    async fn a_data_provider_that_does_not_load_is_an_error() {
+        let simulator = Simulator::new(SimulatorConfig::default()).unwrap();
+        let error = simulate(&simulator, &case(json!({ "max": 10 }), b"not wasm"))
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{error:#}").starts_with("failed to load the data provider"),
+            "{error:#}"
+        );
+    }
+}
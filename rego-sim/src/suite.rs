@@ -0,0 +1,640 @@
+// suite
+// declarative policy tests: cases of intent, params and mocked data with the rule values they expect
+
+use crate::eval::{qualify_entrypoint, rego_value, Evaluator};
+use crate::schema::{load_schema, validate_params};
+use crate::{data_document, parse_intent_with_abis, AbiRegistry, AbiSpec};
+use anyhow::{bail, Context};
+use op_sim::determinism::json_differences;
+use op_sim::metrics::millis;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// File holding the defaults every case of a suite starts from.
+pub const SUITE_FILE: &str = "suite.json";
+/// Suffix of case files.
+pub const CASE_SUFFIX: &str = ".test.json";
+
+/// Fields a case can set, and `suite.json` can default. `intent`, `params`
+/// and `data` are given inline, or as the path of a JSON file relative to
+/// the file that names it in `intent_file`, `params_file` or `data_file`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CaseFile {
+    description: Option<String>,
+    /// path of the Rego policy
+    policy: Option<PathBuf>,
+    entrypoint: Option<String>,
+    /// the intent as submitted, before parsing
+    intent: Option<Value>,
+    intent_file: Option<PathBuf>,
+    /// contract ABIs to decode the intent's calldata with, as `[[CHAIN_ID:]ADDRESS=]PATH`
+    #[serde(default)]
+    abis: Vec<AbiSpec>,
+    params: Option<Value>,
+    params_file: Option<PathBuf>,
+    /// path of a JSON Schema the params must match, such as the policy's params_schema.json
+    params_schema: Option<PathBuf>,
+    /// mocked data provider output, seen by the policy as `data.data`
+    data: Option<Value>,
+    data_file: Option<PathBuf>,
+    /// expected value of the entrypoint
+    expect: Option<Value>,
+    /// expected values of other rules, by rule path
+    #[serde(default)]
+    rules: BTreeMap<String, Value>,
+    /// rules expected to be undefined
+    #[serde(default)]
+    undefined: Vec<String>,
+    /// expected fields of the parsed intent, as the policy sees them in `input`
+    #[serde(default)]
+    input: BTreeMap<String, Value>,
+}
+
+impl CaseFile {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut file: CaseFile = serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        file.policy = file.policy.map(|policy| dir.join(policy));
+        file.params_schema = file.params_schema.map(|schema| dir.join(schema));
+        file.abis = file
+            .abis
+            .into_iter()
+            .map(|spec| spec.relative_to(dir))
+            .collect();
+        let files = [
+            ("intent", &mut file.intent, file.intent_file.take()),
+            ("params", &mut file.params, file.params_file.take()),
+            ("data", &mut file.data, file.data_file.take()),
+        ];
+        for (name, value, value_file) in files {
+            let Some(value_file) = value_file else {
+                continue;
+            };
+            if value.is_some() {
+                bail!(
+                    "{}: set either \"{name}\" or \"{name}_file\", not both",
+                    path.display()
+                );
+            }
+            let value_path = dir.join(value_file);
+            let contents = std::fs::read_to_string(&value_path)
+                .with_context(|| format!("failed to read {}", value_path.display()))?;
+            *value = Some(
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("{} is not valid JSON", value_path.display()))?,
+            );
+        }
+        Ok(file)
+    }
+
+    /// This case, with anything it leaves out taken from the suite defaults.
+    fn or(self, defaults: &CaseFile) -> CaseFile {
+        CaseFile {
+            description: self.description,
+            policy: self.policy.or_else(|| defaults.policy.clone()),
+            entrypoint: self.entrypoint.or_else(|| defaults.entrypoint.clone()),
+            intent: self.intent.or_else(|| defaults.intent.clone()),
+            intent_file: None,
+            abis: if self.abis.is_empty() {
+                defaults.abis.clone()
+            } else {
+                self.abis
+            },
+            params: self.params.or_else(|| defaults.params.clone()),
+            params_file: None,
+            params_schema: self
+                .params_schema
+                .or_else(|| defaults.params_schema.clone()),
+            data: self.data.or_else(|| defaults.data.clone()),
+            data_file: None,
+            expect: self.expect,
+            rules: self.rules,
+            undefined: self.undefined,
+            input: self.input,
+        }
+    }
+}
+
+/// A directory of cases: every `*.test.json` file in it, with defaults from
+/// its `suite.json` if there is one.
+pub struct Suite {
+    pub dir: PathBuf,
+    defaults: CaseFile,
+    cases: Vec<PathBuf>,
+}
+
+impl Suite {
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let defaults_path = dir.join(SUITE_FILE);
+        let defaults = if defaults_path.exists() {
+            CaseFile::load(&defaults_path)?
+        } else {
+            CaseFile::default()
+        };
+        if defaults.expect.is_some()
+            || !defaults.rules.is_empty()
+            || !defaults.undefined.is_empty()
+            || !defaults.input.is_empty()
+        {
+            bail!(
+                "{} holds defaults only; expectations belong in the cases",
+                defaults_path.display()
+            );
+        }
+
+        let mut cases = Vec::new();
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(CASE_SUFFIX))
+            {
+                cases.push(path);
+            }
+        }
+        if cases.is_empty() {
+            bail!("{} has no *{} cases", dir.display(), CASE_SUFFIX);
+        }
+        cases.sort();
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            defaults,
+            cases,
+        })
+    }
+
+    /// Runs every case whose name contains `filter`.
+    pub fn run(&self, filter: Option<&str>) -> SuiteReport {
+        let started = Instant::now();
+        let cases = self
+            .cases
+            .iter()
+            .filter(|path| filter.is_none_or(|filter| case_name(path).contains(filter)))
+            .map(|path| self.run_case(path))
+            .collect();
+        SuiteReport {
+            dir: self.dir.clone(),
+            time_ms: millis(started.elapsed()),
+            cases,
+        }
+    }
+
+    fn run_case(&self, path: &Path) -> CaseReport {
+        let started = Instant::now();
+        let mut report = CaseReport {
+            name: case_name(path),
+            file: path.to_path_buf(),
+            description: None,
+            status: Status::Passed,
+            failures: Vec::new(),
+            error: None,
+            prints: Vec::new(),
+            time_ms: 0.0,
+        };
+        match CaseFile::load(path).and_then(|file| {
+            let file = file.or(&self.defaults);
+            report.description = file.description.clone();
+            check(&file, &mut report)
+        }) {
+            Ok(()) if report.failures.is_empty() => {}
+            Ok(()) => report.status = Status::Failed,
+            Err(e) => {
+                report.status = Status::Error;
+                report.error = Some(format!("{:#}", e));
+            }
+        }
+        report.time_ms = millis(started.elapsed());
+        report
+    }
+}
+
+fn case_name(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    file_name
+        .strip_suffix(CASE_SUFFIX)
+        .unwrap_or(file_name)
+        .to_string()
+}
+
+// evaluates every rule the case has an expectation for, recording each mismatch
+fn check(file: &CaseFile, report: &mut CaseReport) -> anyhow::Result<()> {
+    let Some(policy_path) = &file.policy else {
+        bail!("no policy: set \"policy\" in the case or in {}", SUITE_FILE);
+    };
+    let policy = std::fs::read_to_string(policy_path)
+        .with_context(|| format!("failed to read {}", policy_path.display()))?;
+    let Some(intent) = &file.intent else {
+        bail!("no intent: set \"intent\" in the case or in {}", SUITE_FILE);
+    };
+    let abis = AbiRegistry::load(&file.abis)?;
+    let input = serde_json::to_value(
+        parse_intent_with_abis(intent.clone(), &abis).context("failed to parse intent")?,
+    )?;
+    let params = file
+        .params
+        .clone()
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    if let Some(schema_path) = &file.params_schema {
+        validate_params(&params, &load_schema(schema_path)?)?;
+    }
+    let data = data_document(params, file.data.clone());
+
+    let mut expectations: Vec<(String, Option<&Value>)> = Vec::new();
+    if let Some(expect) = &file.expect {
+        let Some(entrypoint) = &file.entrypoint else {
+            bail!(
+                "\"expect\" needs an entrypoint: set \"entrypoint\" in the case or in {}",
+                SUITE_FILE
+            );
+        };
+        expectations.push((qualify_entrypoint(entrypoint), Some(expect)));
+    }
+    expectations.extend(
+        file.rules
+            .iter()
+            .map(|(rule, value)| (qualify_entrypoint(rule), Some(value))),
+    );
+    expectations.extend(
+        file.undefined
+            .iter()
+            .map(|rule| (qualify_entrypoint(rule), None)),
+    );
+    if expectations.is_empty() && file.input.is_empty() {
+        bail!("the case expects nothing: set \"expect\", \"rules\", \"undefined\" or \"input\"");
+    }
+
+    for (field, expected) in &file.input {
+        let path = format!("input.{field}");
+        match input.get(field) {
+            Some(actual) => json_differences(&path, expected, actual, &mut report.failures),
+            None => report
+                .failures
+                .push(format!("{path}: {expected} != (undefined)")),
+        }
+    }
+
+    let mut evaluator = Evaluator::new(&policy, &input, &data)?;
+    for (rule, expected) in expectations {
+        let decision = evaluator.rule(&rule)?;
+        report.prints.extend(decision.prints);
+        match (expected, decision.value) {
+            (Some(expected), Some(actual)) => {
+                json_differences(&rule, &rego_value(expected)?, &actual, &mut report.failures)
+            }
+            (Some(expected), None) => report
+                .failures
+                .push(format!("{rule}: {expected} != (undefined)")),
+            (None, Some(actual)) => report
+                .failures
+                .push(format!("{rule}: (undefined) != {actual}")),
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Passed,
+    /// a rule did not have the expected value
+    Failed,
+    /// the case could not be evaluated
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaseReport {
+    pub name: String,
+    pub file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub status: Status,
+    /// one `rule.path: expected != actual` line per mismatch
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// output of Rego `print` calls
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub prints: Vec<String>,
+    pub time_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SuiteReport {
+    pub dir: PathBuf,
+    pub time_ms: f64,
+    pub cases: Vec<CaseReport>,
+}
+
+impl SuiteReport {
+    pub fn count(&self, status: Status) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| case.status == status)
+            .count()
+    }
+
+    pub fn print_human(&self) {
+        println!("{} ({} case(s))", self.dir.display(), self.cases.len());
+        for case in &self.cases {
+            match case.status {
+                Status::Passed => println!("  ok     {}", case.name),
+                Status::Failed => {
+                    println!("  FAIL   {}", case.name);
+                    for failure in &case.failures {
+                        println!("           {}", failure);
+                    }
+                }
+                Status::Error => {
+                    println!("  ERROR  {}", case.name);
+                    for line in case.error.as_deref().unwrap_or_default().lines() {
+                        println!("           {}", line);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reports of every suite run, as written by `--json`.
+#[derive(Debug, Serialize)]
+pub struct TestReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub errors: usize,
+    pub suites: Vec<SuiteReport>,
+}
+
+impl TestReport {
+    pub fn new(suites: Vec<SuiteReport>) -> Self {
+        let total = |status| suites.iter().map(|suite| suite.count(status)).sum();
+        Self {
+            passed: total(Status::Passed),
+            failed: total(Status::Failed),
+            errors: total(Status::Error),
+            suites,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed == 0 && self.errors == 0
+    }
+
+    /// The report as JUnit XML, one `<testsuite>` per suite directory.
+    pub fn junit(&self) -> String {
+        let time = |ms: f64| format!("{:.3}", ms / 1000.0);
+        let total_ms: f64 = self.suites.iter().map(|suite| suite.time_ms).sum();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites name=\"rego-sim\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">\n",
+            self.passed + self.failed + self.errors,
+            self.failed,
+            self.errors,
+            time(total_ms)
+        ));
+        for suite in &self.suites {
+            let name = escape(&suite.dir.display().to_string());
+            xml.push_str(&format!(
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{}\">\n",
+                name,
+                suite.cases.len(),
+                suite.count(Status::Failed),
+                suite.count(Status::Error),
+                time(suite.time_ms)
+            ));
+            for case in &suite.cases {
+                xml.push_str(&format!(
+                    "    <testcase name=\"{}\" classname=\"{}\" file=\"{}\" time=\"{}\"",
+                    escape(&case.name),
+                    name,
+                    escape(&case.file.display().to_string()),
+                    time(case.time_ms)
+                ));
+                if case.status == Status::Passed && case.prints.is_empty() {
+                    xml.push_str("/>\n");
+                    continue;
+                }
+                xml.push_str(">\n");
+                match case.status {
+                    Status::Passed => {}
+                    Status::Failed => xml.push_str(&format!(
+                        "      <failure message=\"{} mismatch(es)\">{}</failure>\n",
+                        case.failures.len(),
+                        escape(&case.failures.join("\n"))
+                    )),
+                    Status::Error => {
+                        let error = escape(case.error.as_deref().unwrap_or_default());
+                        xml.push_str(&format!(
+                            "      <error message=\"{}\">{}</error>\n",
+                            error, error
+                        ));
+                    }
+                }
+                if !case.prints.is_empty() {
+                    xml.push_str(&format!(
+                        "      <system-out>{}</system-out>\n",
+                        escape(&case.prints.join("\n"))
+                    ));
+                }
+                xml.push_str("    </testcase>\n");
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const POLICY: &str = "package example\n\ndefault allow := false\n\nallow if data.data.price < data.params.max\n\nlabel := data.data\n";
+
+    const INTENT: &str = r#"{
+        "from": "0xF2168be2F3aE8989696705b211c7b9d65E3416dA",
+        "to": "0x8f86403A4DE0BB5791fa46B8e795C547942fE4Cf",
+        "value": "0x0",
+        "data": "0x",
+        "chainId": "1"
+    }"#;
+
+    // a suite directory holding `files`, removed when dropped
+    struct TempSuite(PathBuf);
+
+    impl TempSuite {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("rego-sim-suite-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in files {
+                std::fs::write(dir.join(file), contents).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempSuite {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn suite_json(data: Value) -> String {
+        json!({
+            "policy": "policy.rego",
+            "entrypoint": "example.allow",
+            "intent_file": "intent.json",
+            "params": { "max": 10 },
+            "data": data,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn inline_strings_stay_literal_and_files_are_read_from_file_keys() {
+        let suite = TempSuite::new(
+            "literal",
+            &[
+                ("policy.rego", POLICY),
+                ("intent.json", INTENT),
+                ("cheap.json", r#"{"price": 5}"#),
+                ("suite.json", &suite_json(json!("cheap.json"))),
+                (
+                    "literal.test.json",
+                    r#"{"rules": {"example.label": "cheap.json"}}"#,
+                ),
+                (
+                    "from_file.test.json",
+                    r#"{"data_file": "cheap.json", "expect": true}"#,
+                ),
+            ],
+        );
+        let report = Suite::load(&suite.0).unwrap().run(None);
+        assert_eq!(report.count(Status::Passed), 2, "{:#?}", report.cases);
+    }
+
+    #[test]
+    fn a_value_and_its_file_cannot_both_be_set() {
+        let suite = TempSuite::new(
+            "conflict",
+            &[
+                ("policy.rego", POLICY),
+                ("intent.json", INTENT),
+                ("suite.json", &suite_json(json!({ "price": 5 }))),
+                (
+                    "both.test.json",
+                    r#"{"data": {}, "data_file": "cheap.json", "expect": true}"#,
+                ),
+            ],
+        );
+        let report = Suite::load(&suite.0).unwrap().run(None);
+        let case = &report.cases[0];
+        assert_eq!(case.status, Status::Error);
+        assert!(
+            case.error
+                .as_deref()
+                .unwrap()
+                .ends_with(r#"set either "data" or "data_file", not both"#),
+            "{:?}",
+            case.error
+        );
+
+        let defaults = TempSuite::new(
+            "conflict-defaults",
+            &[
+                ("intent.json", INTENT),
+                (
+                    "suite.json",
+                    r#"{"intent": {}, "intent_file": "intent.json"}"#,
+                ),
+                ("case.test.json", r#"{"expect": true}"#),
+            ],
+        );
+        assert!(Suite::load(&defaults.0).is_err());
+    }
+
+    #[test]
+    fn reports_failures_errors_and_passes() {
+        let suite = TempSuite::new(
+            "statuses",
+            &[
+                ("policy.rego", POLICY),
+                ("intent.json", INTENT),
+                ("suite.json", &suite_json(json!({ "price": 5 }))),
+                (
+                    "allowed.test.json",
+                    r#"{"expect": true, "input": {"chain_id": 1}}"#,
+                ),
+                (
+                    "wrong.test.json",
+                    r#"{"data": {"price": 50}, "expect": true, "undefined": ["example.label"]}"#,
+                ),
+                ("nothing.test.json", r#"{"description": "expects nothing"}"#),
+            ],
+        );
+        let report = Suite::load(&suite.0).unwrap().run(None);
+        let status = |name: &str| report.cases.iter().find(|case| case.name == name).unwrap();
+        assert_eq!(status("allowed").status, Status::Passed);
+        assert_eq!(status("wrong").status, Status::Failed);
+        assert_eq!(
+            status("wrong").failures,
+            [
+                "data.example.allow: true != false",
+                r#"data.example.label: (undefined) != {"price":50}"#,
+            ]
+        );
+        assert_eq!(status("nothing").status, Status::Error);
+
+        let filtered = Suite::load(&suite.0).unwrap().run(Some("allow"));
+        assert_eq!(filtered.cases.len(), 1);
+        assert!(!TestReport::new(vec![report]).is_success());
+        assert!(TestReport::new(vec![filtered]).is_success());
+    }
+
+    #[test]
+    fn suite_defaults_cannot_hold_expectations() {
+        let suite = TempSuite::new(
+            "defaults",
+            &[
+                ("suite.json", r#"{"expect": true}"#),
+                ("case.test.json", "{}"),
+            ],
+        );
+        let error = Suite::load(&suite.0).err().unwrap();
+        assert!(error.to_string().contains("holds defaults only"), "{error}");
+    }
+
+    #[test]
+    fn escapes_junit_text() {
+        assert_eq!(
+            escape(r#"<a href="x">&'"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&apos;"
+        );
+    }
+}